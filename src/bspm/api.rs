@@ -0,0 +1,151 @@
+use std::future::Future;
+
+use anyhow::{bail, Context};
+use isahc::prelude::*;
+use isahc::{AsyncBody, Response};
+use serde_json::Value;
+
+use super::USER_AGENT;
+
+// Upper bound of release list pages fetched from an API
+const RELEASE_PAGES: usize = 10;
+
+/// Send a GET request to the API of a release host
+pub async fn send(url: &str, headers: &[(&str, String)]) -> anyhow::Result<Response<AsyncBody>> {
+    let mut request = isahc::Request::get(url).header("User-Agent", USER_AGENT);
+    for (name, value) in headers {
+        request = request.header(*name, value.as_str());
+    }
+    request
+        .body(())
+        .context("Failed to build request body")?
+        .send_async()
+        .await
+        .context(url.to_string())
+}
+
+/// The JSON body of a response, unauthenticated requests may have failed for a private repository
+pub async fn json(
+    mut response: Response<AsyncBody>,
+    url: &str,
+    authenticated: bool,
+) -> anyhow::Result<Value> {
+    match response.status().as_u16() {
+        200 => Ok(response.json().await?),
+        401 | 403 | 404 if !authenticated => bail!(
+            "Status: {}\nURL: {}\nPrivate repositories need a token",
+            response.status(),
+            url
+        ),
+        _ => bail!("Status: {}\nURL: {}", response.status(), url),
+    }
+}
+
+/// Entries of a paginated list, `url` already asks for pages of `size` entries
+pub async fn pages<F, R>(url: &str, size: usize, get: F) -> anyhow::Result<Vec<Value>>
+where
+    F: Fn(String) -> R,
+    R: Future<Output = anyhow::Result<Value>>,
+{
+    let mut entries = Vec::new();
+    for page in 1..=RELEASE_PAGES {
+        let page_url = format!("{}&page={}", url, page);
+        let page = match get(page_url).await? {
+            Value::Array(v) => v,
+            _ => bail!("Invalid release list: {}", url),
+        };
+        let last = page.len() < size;
+        entries.extend(page);
+        if last {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    // Answers every request with the body of the matching path or 404 and records the
+    // request heads
+    pub fn serve(routes: Vec<(&'static str, &'static str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut head = String::new();
+                let mut reader = BufReader::new(&stream);
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head += &line;
+                }
+                let path = head.split_whitespace().nth(1).unwrap_or("").to_string();
+                recorded.lock().unwrap().push(head.to_lowercase());
+                let (status, body) = match routes.iter().find(|(p, _)| *p == path) {
+                    Some((_, body)) => ("200 OK", *body),
+                    None => ("404 Not Found", ""),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (format!("http://{}", address), requests)
+    }
+
+    async fn get(url: String, authenticated: bool) -> anyhow::Result<Value> {
+        json(send(&url, &[]).await?, &url, authenticated).await
+    }
+
+    #[test]
+    fn paginate() {
+        let (server, requests) = serve(vec![
+            ("/api/releases?limit=2&page=1", "[1, 2]"),
+            ("/api/releases?limit=2&page=2", "[3]"),
+            ("/api/releases?limit=2&page=3", "[4]"),
+            ("/api/object?limit=2&page=1", "{}"),
+        ]);
+        let entries = smol::block_on(pages(
+            &format!("{}/api/releases?limit=2", server),
+            2,
+            |url| get(url, true),
+        ))
+        .unwrap();
+        assert_eq!(entries, [1, 2, 3]);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        let object = smol::block_on(pages(&format!("{}/api/object?limit=2", server), 2, |url| {
+            get(url, true)
+        }));
+        assert!(object.is_err());
+    }
+
+    #[test]
+    fn private_hint() {
+        let (server, _) = serve(Vec::new());
+        for (authenticated, hint) in [(false, true), (true, false)] {
+            let err =
+                smol::block_on(get(format!("{}/missing", server), authenticated)).unwrap_err();
+            assert_eq!(
+                err.to_string()
+                    .contains("Private repositories need a token"),
+                hint
+            );
+        }
+    }
+}
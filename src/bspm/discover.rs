@@ -0,0 +1,171 @@
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use isahc::config::RedirectPolicy;
+use isahc::prelude::*;
+use regex::Regex;
+use serde_json::Value;
+
+use super::{
+    package::Release,
+    source::{Candidate, Source},
+    USER_AGENT,
+};
+
+/// How versions are read from the document at a version endpoint
+pub enum Extract {
+    /// JSON pointer like `/tag_name` to a version or an array of versions
+    Json(String),
+    /// Every match is a version, or the first capture group if there is one
+    Regex(Regex),
+}
+
+/// Versions published on a web page or JSON API of a vendor, without any downloads
+pub struct Discover {
+    url: String,
+    extract: Extract,
+}
+
+impl Discover {
+    pub fn json(url: &str, pointer: &str) -> anyhow::Result<Discover> {
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            bail!(
+                "JSON pointers start with `/`, like `/tag_name`: {}",
+                pointer
+            )
+        }
+        Ok(Discover {
+            url: url.to_string(),
+            extract: Extract::Json(pointer.to_string()),
+        })
+    }
+
+    pub fn regex(url: &str, pattern: &str) -> anyhow::Result<Discover> {
+        Ok(Discover {
+            url: url.to_string(),
+            extract: Extract::Regex(
+                Regex::new(pattern).context(format!("Invalid version regex: {}", pattern))?,
+            ),
+        })
+    }
+
+    async fn versions(&self) -> anyhow::Result<Vec<String>> {
+        let mut response = isahc::Request::get(&self.url)
+            .header("User-Agent", USER_AGENT)
+            .redirect_policy(RedirectPolicy::Limit(10))
+            .body(())
+            .context("Failed to build request body")?
+            .send_async()
+            .await
+            .context(self.url.to_string())?;
+        if !response.status().is_success() {
+            bail!("Status: {}\nURL: {}", response.status(), self.url)
+        }
+        let text = response.text().await?;
+        self.extract(&text)
+    }
+
+    fn extract(&self, text: &str) -> anyhow::Result<Vec<String>> {
+        let versions: Vec<String> = match &self.extract {
+            Extract::Json(pointer) => {
+                let json: Value =
+                    serde_json::from_str(text).context(format!("Invalid JSON: {}", self.url))?;
+                match json.pointer(pointer) {
+                    Some(Value::String(v)) => vec![v.to_string()],
+                    Some(Value::Array(v)) => v
+                        .iter()
+                        .filter_map(|v| v.as_str().map(str::to_string))
+                        .collect(),
+                    Some(Value::Number(v)) => vec![v.to_string()],
+                    _ => bail!("No version at `{}` in {}", pointer, self.url),
+                }
+            }
+            Extract::Regex(regex) => regex
+                .captures_iter(text)
+                .filter_map(|c| c.get(1).or_else(|| c.get(0)))
+                .map(|m| m.as_str().to_string())
+                .collect(),
+        };
+        if versions.is_empty() {
+            bail!("No version found at {}", self.url)
+        }
+        Ok(versions)
+    }
+
+    // Vendor pages say nothing about prereleases, guessing from the version would hide date
+    // versions like `2024-01-05` from the stable channel
+    fn release_of(&self, tag: String) -> anyhow::Result<Candidate> {
+        if tag.trim().is_empty() {
+            bail!("Empty version found at {}", self.url)
+        }
+        Ok(Candidate {
+            prerelease: false,
+            release: Release::Version(tag.trim().to_string()),
+            published: None,
+            artifacts: Vec::new(),
+            checksum: None,
+        })
+    }
+}
+
+#[async_trait]
+impl Source for Discover {
+    fn describe(&self) -> &'static str {
+        "version endpoint"
+    }
+
+    async fn releases(&self) -> anyhow::Result<Vec<Candidate>> {
+        let mut versions = self.versions().await?;
+        versions.dedup();
+        versions.into_iter().map(|v| self.release_of(v)).collect()
+    }
+
+    async fn release(&self, tag: &str) -> anyhow::Result<Candidate> {
+        self.release_of(tag.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://example.com/versions";
+
+    #[test]
+    fn extract_json() {
+        let text = r#"{"tag_name": "v1.2.3", "all": ["2.0", {"x": 1}, "1.9"], "build": 42}"#;
+        for (pointer, expected) in [
+            ("/tag_name", vec!["v1.2.3"]),
+            ("/all", vec!["2.0", "1.9"]),
+            ("/build", vec!["42"]),
+        ] {
+            let discover = Discover::json(URL, pointer).unwrap();
+            assert_eq!(discover.extract(text).unwrap(), expected);
+        }
+        let missing = Discover::json(URL, "/name").unwrap();
+        assert!(missing.extract(text).is_err());
+        assert!(missing.extract("<html>").is_err());
+        assert!(Discover::json(URL, "tag_name").is_err());
+    }
+
+    #[test]
+    fn extract_regex() {
+        let page = "<a href=\"tool-1.2.0.tar.gz\">1.2.0</a> <a href=\"tool-1.3.0-rc1.tar.gz\">";
+        let capture = Discover::regex(URL, r#"tool-([0-9][^"]*)\.tar\.gz"#).unwrap();
+        assert_eq!(capture.extract(page).unwrap(), ["1.2.0", "1.3.0-rc1"]);
+        let whole = Discover::regex(URL, r"\d+\.\d+\.\d+").unwrap();
+        assert_eq!(whole.extract(page).unwrap(), ["1.2.0", "1.2.0", "1.3.0"]);
+        assert!(capture.extract("nothing here").is_err());
+        assert!(Discover::regex(URL, "(").is_err());
+    }
+
+    #[test]
+    fn stable_versions() {
+        let discover = Discover::json(URL, "").unwrap();
+        for tag in ["1.3.0-rc1", " 1.2.0\n", "2024-01-05"] {
+            let release = discover.release_of(tag.to_string()).unwrap();
+            assert!(!release.prerelease, "{}", tag);
+            assert_eq!(release.release.to_string(), tag.trim());
+        }
+        assert!(discover.release_of(" ".to_string()).is_err());
+    }
+}
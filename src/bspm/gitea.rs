@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::{
+    api, github,
+    installer::Credentials,
+    source::{Candidate, Source},
+    Forge, Settings,
+};
+
+// Gitea caps the page size at 50 by default
+const PAGE_SIZE: usize = 50;
+
+/// Releases of a repository on Gitea or Forgejo, like Codeberg
+pub struct Gitea {
+    host: String,
+    api: String,
+    repo: String,
+    token: Option<String>,
+}
+
+impl Gitea {
+    pub fn new(host: &str, repo: String, settings: &Settings) -> Gitea {
+        Gitea {
+            host: host.to_string(),
+            api: settings.api(host, Forge::Gitea),
+            repo,
+            token: settings.token(host),
+        }
+    }
+
+    async fn get(&self, url: &str) -> anyhow::Result<Value> {
+        let mut headers = vec![("Accept", "application/json".to_string())];
+        if let Some(token) = &self.token {
+            headers.push(("Authorization", format!("token {}", token)));
+        }
+        api::json(api::send(url, &headers).await?, url, self.token.is_some()).await
+    }
+}
+
+#[async_trait]
+impl Source for Gitea {
+    fn describe(&self) -> &'static str {
+        "Gitea repository"
+    }
+
+    async fn releases(&self) -> anyhow::Result<Vec<Candidate>> {
+        let url = format!(
+            "{}/repos/{}/releases?limit={}",
+            self.api, self.repo, PAGE_SIZE
+        );
+        api::pages(&url, PAGE_SIZE, |url| async move { self.get(&url).await })
+            .await?
+            .into_iter()
+            .filter(|r| r["draft"] != Value::Bool(true))
+            .map(candidate)
+            .collect()
+    }
+
+    async fn release(&self, tag: &str) -> anyhow::Result<Candidate> {
+        candidate(
+            self.get(&format!(
+                "{}/repos/{}/releases/tags/{}",
+                self.api, self.repo, tag
+            ))
+            .await?,
+        )
+    }
+
+    fn credentials(&self) -> Option<Credentials> {
+        Some(Credentials {
+            host: self.host.clone(),
+            header: "Authorization".to_string(),
+            value: format!("token {}", self.token.as_ref()?),
+        })
+    }
+}
+
+// Releases come in the shape of GitHub releases, attachments are never replaced in place
+// so their upload time stands in for `updated_at`
+fn candidate(mut release: Value) -> anyhow::Result<Candidate> {
+    if let Some(assets) = release["assets"].as_array_mut() {
+        for asset in assets {
+            asset["updated_at"] = asset["created_at"].clone();
+        }
+    }
+    github::candidate(&release)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bspm::{api::tests::serve, package::Release, Host};
+
+    const RELEASES: &str = r#"[
+        {"tag_name": "v1.1.0-rc.1", "draft": false, "prerelease": true, "assets": []},
+        {"tag_name": "v1.0.0", "draft": false, "prerelease": false,
+         "published_at": "2024-01-02T03:04:05Z",
+         "assets": [{"name": "tool-linux-amd64.tar.gz", "size": 1234,
+                     "created_at": "2024-01-02T03:05:00Z",
+                     "browser_download_url": "https://codeberg.org/o/r/releases/download/v1.0.0/tool-linux-amd64.tar.gz"}]},
+        {"tag_name": "v2.0.0", "draft": true, "prerelease": false, "assets": []}
+    ]"#;
+
+    fn gitea(server: String, token: Option<&str>) -> Gitea {
+        let mut settings = Settings::default();
+        settings.hosts.insert(
+            "codeberg.org".to_string(),
+            Host {
+                kind: Some(Forge::Gitea),
+                api: Some(format!("{}/api/v1", server)),
+                token: token.map(str::to_string),
+            },
+        );
+        Gitea::new("codeberg.org", "o/r".to_string(), &settings)
+    }
+
+    #[test]
+    fn releases() {
+        let (server, requests) = serve(vec![
+            ("/api/v1/repos/o/r/releases?limit=50&page=1", RELEASES),
+            (
+                "/api/v1/repos/o/r/releases/tags/v1.0.0",
+                r#"{"tag_name": "v1.0.0", "assets": []}"#,
+            ),
+        ]);
+        let gitea = gitea(server, Some("secret"));
+        let releases = smol::block_on(gitea.releases()).unwrap();
+        let tags: Vec<String> = releases.iter().map(|c| c.release.to_string()).collect();
+        assert_eq!(tags, ["v1.1.0-rc.1", "v1.0.0"]);
+        assert!(releases[0].prerelease);
+        let stable = &releases[1];
+        assert!(!stable.prerelease);
+        assert_eq!(stable.published, "2024-01-02T03:04:05Z".parse().ok());
+        let artifact = &stable.artifacts[0];
+        assert_eq!(artifact.name, "tool-linux-amd64.tar.gz");
+        assert_eq!(artifact.size, Some(1234));
+        assert_eq!(artifact.updated, "2024-01-02T03:05:00Z".parse().ok());
+        assert!(artifact.url.ends_with("/v1.0.0/tool-linux-amd64.tar.gz"));
+        let tagged = smol::block_on(gitea.release("v1.0.0")).unwrap();
+        assert!(matches!(tagged.release, Release::Version(tag) if tag == "v1.0.0"));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for request in requests.iter() {
+            assert!(request.contains("\r\nauthorization: token secret\r\n"));
+        }
+        let credentials = gitea.credentials().unwrap();
+        assert_eq!(credentials.host, "codeberg.org");
+        assert_eq!(credentials.value, "token secret");
+    }
+
+    #[test]
+    fn private_without_token() {
+        let (server, requests) = serve(Vec::new());
+        let gitea = gitea(server, None);
+        let err = smol::block_on(gitea.releases()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Private repositories need a token"));
+        assert!(!requests.lock().unwrap()[0].contains("authorization"));
+        assert!(gitea.credentials().is_none());
+    }
+}
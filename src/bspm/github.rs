@@ -0,0 +1,144 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use serde_json::Value;
+
+use super::{
+    api,
+    package::Release,
+    source::{Artifact, Candidate, Source},
+    ui::context,
+    Forge, Settings,
+};
+
+const PAGE_SIZE: usize = 100;
+
+/// Releases of a repository on github.com or a GitHub Enterprise Server
+pub struct GitHub {
+    host: String,
+    api: String,
+    repo: String,
+    token: Option<String>,
+}
+
+impl GitHub {
+    pub fn new(host: &str, repo: String, settings: &Settings) -> GitHub {
+        GitHub {
+            host: host.to_string(),
+            api: settings.api(host, Forge::GitHub),
+            repo,
+            token: settings.token(host),
+        }
+    }
+
+    async fn get(&self, url: &str) -> anyhow::Result<Value> {
+        let mut headers = vec![
+            ("Accept", "application/vnd.github+json".to_string()),
+            ("X-GitHub-Api-Version", "2022-11-28".to_string()),
+        ];
+        if let Some(token) = &self.token {
+            headers.push(("Authorization", format!("Bearer {}", token)));
+        }
+        let response = api::send(url, &headers).await?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<i64>().ok())
+        };
+        let remaining = header("X-RateLimit-Remaining");
+        let reset = header("X-RateLimit-Reset")
+            .and_then(|reset| Utc.timestamp_opt(reset, 0).single())
+            .map(DateTime::<Local>::from);
+        let retry =
+            header("Retry-After").map(|secs| Local::now() + chrono::Duration::seconds(secs));
+        let status = response.status().as_u16();
+        if status == 403 || status == 429 {
+            if let Some(until) = retry.or(reset.filter(|_| remaining == Some(0))) {
+                bail!(
+                    "GitHub API rate limited until {}{}",
+                    until.format("%H:%M"),
+                    if self.token.is_none() {
+                        " (set a GitHub token to raise the limit)"
+                    } else {
+                        ""
+                    }
+                )
+            }
+        }
+        if let (Some(remaining), Some(reset), 200) = (remaining, reset, status) {
+            if remaining < 10 {
+                context("🐌", &self.host)
+                    .await
+                    .notify(&format!(
+                        "Only {} GitHub API requests left until {}",
+                        remaining,
+                        reset.format("%H:%M")
+                    ))
+                    .await;
+            }
+        }
+        api::json(response, url, self.token.is_some()).await
+    }
+}
+
+#[async_trait]
+impl Source for GitHub {
+    fn describe(&self) -> &'static str {
+        "GitHub repository"
+    }
+
+    async fn releases(&self) -> anyhow::Result<Vec<Candidate>> {
+        let url = format!(
+            "{}/repos/{}/releases?per_page={}",
+            self.api, self.repo, PAGE_SIZE
+        );
+        api::pages(&url, PAGE_SIZE, |url| async move { self.get(&url).await })
+            .await?
+            .iter()
+            .filter(|r| r["draft"] != Value::Bool(true))
+            .map(candidate)
+            .collect()
+    }
+
+    async fn release(&self, tag: &str) -> anyhow::Result<Candidate> {
+        candidate(
+            &self
+                .get(&format!(
+                    "{}/repos/{}/releases/tags/{}",
+                    self.api, self.repo, tag
+                ))
+                .await?,
+        )
+    }
+}
+
+/// Read a release in the shape of the GitHub API, Gitea and Forgejo use it as well
+pub fn candidate(release: &Value) -> anyhow::Result<Candidate> {
+    let tag = release["tag_name"]
+        .as_str()
+        .ok_or_else(|| anyhow!("No tag name in release"))?;
+    let artifacts = release["assets"]
+        .as_array()
+        .ok_or_else(|| anyhow!("No assets in release"))?
+        .iter()
+        .filter_map(|asset| {
+            Some(Artifact {
+                name: asset["name"].as_str()?.to_string(),
+                url: asset["browser_download_url"].as_str()?.to_string(),
+                size: asset["size"].as_u64(),
+                updated: asset["updated_at"].as_str().and_then(|u| u.parse().ok()),
+            })
+        })
+        .collect();
+    Ok(Candidate {
+        release: Release::Version(tag.to_string()),
+        prerelease: release["prerelease"] == Value::Bool(true),
+        published: release["published_at"]
+            .as_str()
+            .and_then(|p| p.parse().ok()),
+        artifacts,
+        checksum: None,
+    })
+}
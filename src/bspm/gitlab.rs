@@ -0,0 +1,231 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::{
+    api,
+    installer::Credentials,
+    package::Release,
+    source::{Artifact, Candidate, Source},
+    Forge, Settings,
+};
+
+const PAGE_SIZE: usize = 100;
+
+/// Releases of a project on gitlab.com or a self-hosted GitLab
+pub struct GitLab {
+    host: String,
+    api: String,
+    project: String,
+    token: Option<String>,
+}
+
+impl GitLab {
+    pub fn new(host: &str, project: String, settings: &Settings) -> GitLab {
+        GitLab {
+            host: host.to_string(),
+            api: settings.api(host, Forge::GitLab),
+            project,
+            token: settings.token(host),
+        }
+    }
+
+    async fn get(&self, url: &str) -> anyhow::Result<Value> {
+        let mut headers = Vec::new();
+        if let Some(token) = &self.token {
+            headers.push(("PRIVATE-TOKEN", token.clone()));
+        }
+        api::json(api::send(url, &headers).await?, url, self.token.is_some()).await
+    }
+}
+
+#[async_trait]
+impl Source for GitLab {
+    fn describe(&self) -> &'static str {
+        "GitLab project"
+    }
+
+    async fn releases(&self) -> anyhow::Result<Vec<Candidate>> {
+        let url = format!(
+            "{}/projects/{}/releases?per_page={}",
+            self.api,
+            encode(&self.project),
+            PAGE_SIZE
+        );
+        api::pages(&url, PAGE_SIZE, |url| async move { self.get(&url).await })
+            .await?
+            .iter()
+            .map(candidate)
+            .collect()
+    }
+
+    async fn release(&self, tag: &str) -> anyhow::Result<Candidate> {
+        candidate(
+            &self
+                .get(&format!(
+                    "{}/projects/{}/releases/{}",
+                    self.api,
+                    encode(&self.project),
+                    encode(tag)
+                ))
+                .await?,
+        )
+    }
+
+    fn credentials(&self) -> Option<Credentials> {
+        Some(Credentials {
+            host: self.host.clone(),
+            header: "PRIVATE-TOKEN".to_string(),
+            value: self.token.clone()?,
+        })
+    }
+}
+
+// Release asset links are the artifacts, upcoming releases count as prereleases
+fn candidate(release: &Value) -> anyhow::Result<Candidate> {
+    let tag = release["tag_name"]
+        .as_str()
+        .ok_or_else(|| anyhow!("No tag name in release"))?;
+    let artifacts = release["assets"]["links"]
+        .as_array()
+        .map(|links| {
+            links
+                .iter()
+                .filter_map(|link| {
+                    Some(Artifact {
+                        name: link["name"].as_str()?.to_string(),
+                        url: link["direct_asset_url"]
+                            .as_str()
+                            .or_else(|| link["url"].as_str())?
+                            .to_string(),
+                        size: None,
+                        updated: None,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(Candidate {
+        release: Release::Version(tag.to_string()),
+        prerelease: release["upcoming_release"] == Value::Bool(true),
+        published: release["released_at"].as_str().and_then(|p| p.parse().ok()),
+        artifacts,
+        checksum: None,
+    })
+}
+
+// Project paths and tags are passed as a single URL encoded path segment
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bspm::{api::tests::serve, Host};
+
+    const RELEASES: &str = r#"[
+        {"tag_name": "v1.1.0", "upcoming_release": true, "assets": {"links": []}},
+        {"tag_name": "v1.0.0", "upcoming_release": false,
+         "released_at": "2024-01-02T03:04:05Z",
+         "assets": {"links": [
+            {"name": "tool-linux-amd64.tar.gz",
+             "url": "https://gitlab.com/g/s/p/-/jobs/1/artifacts/tool-linux-amd64.tar.gz",
+             "direct_asset_url": "https://gitlab.com/g/s/p/-/releases/v1.0.0/downloads/tool-linux-amd64.tar.gz"},
+            {"name": "tool.sha256", "url": "https://example.com/tool.sha256"},
+            {"url": "https://example.com/unnamed"}
+         ]}}
+    ]"#;
+
+    fn gitlab(server: String, token: Option<&str>) -> GitLab {
+        let mut settings = Settings::default();
+        settings.hosts.insert(
+            "gitlab.com".to_string(),
+            Host {
+                kind: None,
+                api: Some(format!("{}/api/v4", server)),
+                token: token.map(str::to_string),
+            },
+        );
+        GitLab::new("gitlab.com", "g/s/p".to_string(), &settings)
+    }
+
+    #[test]
+    fn candidates() {
+        let releases: Value = serde_json::from_str(RELEASES).unwrap();
+        let upcoming = candidate(&releases[0]).unwrap();
+        assert!(upcoming.prerelease);
+        assert!(upcoming.artifacts.is_empty());
+        let stable = candidate(&releases[1]).unwrap();
+        assert!(!stable.prerelease);
+        assert_eq!(stable.published, "2024-01-02T03:04:05Z".parse().ok());
+        let urls: Vec<(&str, &str)> = stable
+            .artifacts
+            .iter()
+            .map(|a| (a.name.as_str(), a.url.as_str()))
+            .collect();
+        // The direct asset URL wins over the link target, links without a name are skipped
+        assert_eq!(
+            urls,
+            [
+                (
+                    "tool-linux-amd64.tar.gz",
+                    "https://gitlab.com/g/s/p/-/releases/v1.0.0/downloads/tool-linux-amd64.tar.gz"
+                ),
+                ("tool.sha256", "https://example.com/tool.sha256")
+            ]
+        );
+        assert!(candidate(&serde_json::json!({"assets": {}})).is_err());
+        let bare = candidate(&serde_json::json!({"tag_name": "v0.1.0"})).unwrap();
+        assert!(bare.artifacts.is_empty());
+    }
+
+    #[test]
+    fn encoding() {
+        for (raw, encoded) in [
+            ("group/project", "group%2Fproject"),
+            ("group/sub/project", "group%2Fsub%2Fproject"),
+            ("v1.0.0", "v1.0.0"),
+            ("release/2024-01", "release%2F2024-01"),
+            ("v1.0+build 5", "v1.0%2Bbuild%205"),
+            ("100%#?", "100%25%23%3F"),
+        ] {
+            assert_eq!(encode(raw), encoded, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn releases() {
+        let (server, requests) = serve(vec![
+            (
+                "/api/v4/projects/g%2Fs%2Fp/releases?per_page=100&page=1",
+                RELEASES,
+            ),
+            (
+                "/api/v4/projects/g%2Fs%2Fp/releases/release%2F1.0",
+                r#"{"tag_name": "release/1.0"}"#,
+            ),
+        ]);
+        let gitlab = gitlab(server, Some("secret"));
+        let releases = smol::block_on(gitlab.releases()).unwrap();
+        let tags: Vec<String> = releases.iter().map(|c| c.release.to_string()).collect();
+        assert_eq!(tags, ["v1.1.0", "v1.0.0"]);
+        let tagged = smol::block_on(gitlab.release("release/1.0")).unwrap();
+        assert_eq!(tagged.release.to_string(), "release/1.0");
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for request in requests.iter() {
+            assert!(request.contains("\r\nprivate-token: secret\r\n"));
+        }
+        let credentials = gitlab.credentials().unwrap();
+        assert_eq!(credentials.header, "PRIVATE-TOKEN");
+        assert_eq!(credentials.value, "secret");
+    }
+}
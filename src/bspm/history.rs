@@ -0,0 +1,146 @@
+use anyhow::Context;
+use async_std::fs::{copy, create_dir_all, remove_dir_all};
+use chrono::prelude::*;
+use std::path::{Path, PathBuf};
+
+use super::{data_path, installer::Binary, package::Release};
+
+/// A copy of an installed release kept around for reverting
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub release: Release,
+    pub installed: DateTime<Utc>,
+    pub path: PathBuf,
+    /// The files the release installed next to the main one
+    #[serde(default)]
+    pub binaries: Vec<Binary>,
+}
+
+pub async fn history_path(name: &str) -> PathBuf {
+    let mut result = data_path().await;
+    result.push("history");
+    result.push(name);
+    result
+}
+
+/// Copy the installed files into the history store of a package, the snapshot points at the first
+pub async fn save(
+    name: &str,
+    release: &Release,
+    files: &[&Path],
+    binaries: &[Binary],
+) -> anyhow::Result<Snapshot> {
+    let mut dir = history_path(name).await;
+    dir.push(key(release));
+    create_dir_all(&dir)
+        .await
+        .context(format!("Failed to create history dir: {}", dir.display()))?;
+    let mut saved = Vec::new();
+    for file in files {
+        let path = dir.join(file.file_name().expect("Install path was not a file name"));
+        copy(file, &path)
+            .await
+            .context(format!("Failed to save {}", path.display()))?;
+        saved.push(path);
+    }
+    Ok(Snapshot {
+        release: release.clone(),
+        installed: Utc::now(),
+        path: saved.swap_remove(0),
+        binaries: binaries.to_vec(),
+    })
+}
+
+/// A tree is kept unpacked, the snapshot just points at its version dir
+pub fn keep(release: &Release, dir: PathBuf, binaries: &[Binary]) -> Snapshot {
+    Snapshot {
+        release: release.clone(),
+        installed: Utc::now(),
+        path: dir,
+        binaries: binaries.to_vec(),
+    }
+}
+
+/// Drop the oldest snapshots until only `keep` are left, the installed release is never dropped
+/// as a tree still links into it
+pub async fn prune(
+    history: &mut Vec<Snapshot>,
+    keep: usize,
+    current: Option<&Release>,
+) -> anyhow::Result<()> {
+    while history.len() > keep.max(1) {
+        let oldest = match history.iter().position(|s| Some(&s.release) != current) {
+            Some(i) => i,
+            None => break,
+        };
+        let snapshot = history.remove(oldest);
+        let dir = match snapshot.path.is_dir() {
+            true => Some(snapshot.path.as_path()),
+            false => snapshot.path.parent(),
+        };
+        if let Some(dir) = dir {
+            if dir.exists() {
+                remove_dir_all(dir)
+                    .await
+                    .context(format!("Failed to remove {}", dir.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn remove(name: &str) -> anyhow::Result<()> {
+    let path = history_path(name).await;
+    if path.exists() {
+        remove_dir_all(&path)
+            .await
+            .context(format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Directory name of a release inside the history store
+pub fn key(release: &Release) -> String {
+    let name = match release {
+        Release::Version(v) => v.clone(),
+        Release::Dated(d) => d.format("%Y%m%dT%H%M%S%.f").to_string(),
+    };
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_trees() {
+        let dir = std::env::temp_dir().join("blindspot-test-prune");
+        let mut history: Vec<Snapshot> = ["1.0.0", "1.1.0", "1.2.0"]
+            .iter()
+            .map(|v| {
+                let path = dir.join(v);
+                std::fs::create_dir_all(path.join("bin")).unwrap();
+                keep(&Release::Version(v.to_string()), path, &[])
+            })
+            .collect();
+        let current = Release::Version("1.0.0".to_string());
+        smol::block_on(async {
+            prune(&mut history, 2, Some(&current)).await.unwrap();
+            let kept: Vec<String> = history.iter().map(|s| s.release.to_string()).collect();
+            assert_eq!(kept, ["1.0.0", "1.2.0"]);
+            assert!(dir.join("1.0.0").exists());
+            assert!(!dir.join("1.1.0").exists());
+            prune(&mut history, 1, Some(&current)).await.unwrap();
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].release, current);
+            assert!(dir.join("1.0.0/bin").exists());
+            assert!(!dir.join("1.2.0").exists());
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,42 @@
 use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Poll};
 use std::time::Duration;
 
-use anyhow::Context;
-use async_compression::futures::write::{BzDecoder, GzipDecoder, XzDecoder};
-use async_std::fs::{copy, create_dir, remove_file, set_permissions, File, OpenOptions};
-use async_std::os::unix::fs::OpenOptionsExt;
+use anyhow::{bail, Context};
+use async_compression::futures::bufread::{
+    BrotliDecoder, BzDecoder, GzipDecoder, Lz4Decoder, XzDecoder, ZstdDecoder,
+};
+use async_std::fs::{
+    copy, create_dir, create_dir_all, hard_link, read_dir, remove_dir_all, remove_file, rename,
+    set_permissions, File, OpenOptions,
+};
+use async_std::io::{BufReader, Cursor, Read};
+use async_std::os::unix::fs::{symlink, OpenOptionsExt};
 use async_std::prelude::*;
 use async_tar::Archive;
+use async_zip::base::read::seek::ZipFileReader;
+use async_zip::ZipEntry;
 use isahc::config::RedirectPolicy;
+use isahc::http::Uri;
 use isahc::prelude::*;
-use smol::{self, Timer};
+use isahc::{AsyncBody, Response};
+use smol::{self, Task, Timer};
 
-use super::{data_path, ui};
+use super::{
+    history,
+    openpgp::OpenPgp,
+    package::Release,
+    pattern,
+    sniff::{self, Format},
+    ui,
+    verify::{Checksum, Digests, Signature},
+};
+
+const MAX_REDIRECTS: usize = 50;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Installer {
@@ -21,13 +44,305 @@ pub struct Installer {
     pub path: PathBuf,
     pub compression: Option<Compression>,
     pub archive: Option<Archived>,
+    #[serde(default, skip_serializing)]
     pub backup: Option<PathBuf>,
+    pub entry: Option<String>,
+    /// More files installed from the same archive
+    #[serde(default)]
+    pub binaries: Vec<Binary>,
+    /// Unpack the whole archive and link the installed files into it
+    #[serde(default)]
+    pub tree: Option<Tree>,
+    pub checksum: Option<Checksum>,
+    #[serde(default)]
+    pub validators: Validators,
+    #[serde(skip)]
+    pub credentials: Option<Credentials>,
+    /// Files of the package replaced by a forced reinstall, they may be overwritten
+    #[serde(skip)]
+    pub replaces: Vec<PathBuf>,
+    /// Targets staged by the installation in progress, their stages are removed afterwards
+    #[serde(skip)]
+    pub staged: Vec<PathBuf>,
+    /// Version dir of a tree unpacked by the installation in progress, removed if it fails
+    #[serde(skip)]
+    pub unpacked: Option<PathBuf>,
+}
+
+/// What the last download looked like, unchanged files are not installed again
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub sha256: Option<String>,
+}
+
+/// Another file of the archive and where it is installed, like a plugin next to the main binary
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Binary {
+    pub entry: String,
+    pub path: PathBuf,
+}
+
+/// Archives unpacked as a whole into `<dir>/<version>`, for tools that need their bundled `lib/`
+/// or `share/` dirs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tree {
+    pub dir: PathBuf,
+    /// Directory of the archive to unpack instead of everything, like `node-v*-linux-x64`
+    pub root: Option<String>,
+}
+
+impl Tree {
+    // Unpacking happens next to the version dirs to make the final rename atomic
+    fn staging(&self) -> PathBuf {
+        self.dir.join(".blindspot")
+    }
+
+    // Where an unpacked version waits while it is replaced, until the links are installed
+    fn replaced(&self, key: &str) -> PathBuf {
+        self.dir.join(format!(".{}.old", key))
+    }
+}
+
+// What came along with a download
+struct Downloaded {
+    validators: Validators,
+    content_type: Option<String>,
+    file_name: Option<String>,
+}
+
+impl Downloaded {
+    // The sha256 is filled in once the body is read
+    fn new(response: &Response<AsyncBody>) -> Downloaded {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Downloaded {
+            validators: Validators {
+                etag: header("ETag"),
+                last_modified: header("Last-Modified"),
+                sha256: None,
+            },
+            content_type: header("Content-Type"),
+            file_name: header("Content-Disposition").and_then(|v| sniff::file_name(&v)),
+        }
+    }
+}
+
+// Where the decompressed download ended up
+enum Unpacked {
+    /// In the stages of the installed files, along with the extracted entries (none for a
+    /// plain executable)
+    Staged(Vec<(String, PathBuf)>),
+    /// In a temp file, for archives that need the picker or seeking
+    Spooled(Archived, PathBuf),
+    /// The remembered entry does not match exactly one file of the archive
+    Ambiguous,
+    /// Unpacked as a whole into the staging dir of the tree
+    Tree(Archived),
+}
+
+// Hashes everything read through it
+struct Hashing<R> {
+    inner: R,
+    digests: Digests,
+}
+
+impl<R: Read + Unpin> Read for Hashing<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        let n = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.digests.update(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// A header sent along with requests to a single host, e.g. a private token
+#[derive(Clone)]
+pub struct Credentials {
+    pub host: String,
+    pub header: String,
+    pub value: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: *** (for {})", self.header, self.host)
+    }
 }
 
 impl Installer {
-    pub async fn install(&mut self, ctx: &ui::Context) -> anyhow::Result<()> {
-        let archive = self.guess_archive();
-        let compression = self.guess_compression();
+    /// Download and install the file, returns `false` if `conditional` is set and the file is
+    /// unchanged since the last download
+    pub async fn install(
+        &mut self,
+        ctx: &ui::Context,
+        release: &Release,
+        signature: Option<&Signature>,
+        mut openpgp: Option<&mut OpenPgp>,
+        conditional: bool,
+    ) -> anyhow::Result<bool> {
+        let mut pick = false;
+        loop {
+            let result = self
+                .fetch(
+                    ctx,
+                    release,
+                    signature,
+                    openpgp.as_deref_mut(),
+                    conditional,
+                    pick,
+                )
+                .await;
+            self.clean_up(ctx, result.is_err()).await?;
+            match result? {
+                Some(installed) => return Ok(installed),
+                // The remembered entry is of no help, the picker needs the whole archive
+                None => pick = true,
+            }
+        }
+    }
+
+    // Download, hash, decompress and extract in one pass, returns `None` if the remembered
+    // entry does not match exactly one file of the archive
+    async fn fetch(
+        &mut self,
+        ctx: &ui::Context,
+        release: &Release,
+        signature: Option<&Signature>,
+        openpgp: Option<&mut OpenPgp>,
+        conditional: bool,
+        pick: bool,
+    ) -> anyhow::Result<Option<bool>> {
+        ctx.notify(&format!("Fetching {}", &self.url)).await;
+        let mut response = match self.request(conditional).await? {
+            Some(response) => response,
+            None => {
+                ctx.notify("Server reports the file as unchanged").await;
+                return Ok(Some(false));
+            }
+        };
+        let mut downloaded = Downloaded::new(&response);
+        let done = Arc::new(AtomicBool::new(false));
+        let progresser = self.progress(ctx.clone(), &response, done.clone());
+        let mut body = Hashing {
+            inner: response.body_mut(),
+            digests: Digests::default(),
+        };
+        // Signatures are verified over the whole download, so it has to be on disk first. So is
+        // a checked tree, nothing of it is unpacked before the checksum matches
+        let buffered = signature.is_some()
+            || openpgp.is_some()
+            || (self.tree.is_some() && self.checksum.is_some());
+        let unpacked = if buffered {
+            let (mut download, _) = self.tmp_file(".download").await?;
+            async_std::io::copy(&mut body, &mut download).await?;
+            download.flush().await?;
+            None
+        } else {
+            Some(self.unpack(ctx, &mut body, &downloaded, pick).await?)
+        };
+        // The hash covers the whole download, also what follows the extracted entry
+        async_std::io::copy(&mut body, &mut async_std::io::sink()).await?;
+        done.store(true, Ordering::Relaxed);
+        progresser.await;
+        let digests = body.digests;
+        downloaded.validators.sha256 = Some(digests.sha256());
+        ctx.notify(&format!("Downloaded file has sha256:{}", digests.sha256()))
+            .await;
+        if conditional && downloaded.validators.sha256 == self.validators.sha256 {
+            ctx.notify("Downloaded file is unchanged").await;
+            self.validators = downloaded.validators;
+            return Ok(Some(false));
+        }
+        match &self.checksum {
+            Some(checksum) => {
+                checksum
+                    .verify(&digests)
+                    .context("Refusing to install the downloaded file")?;
+                ctx.notify("Checksum verified").await;
+            }
+            None => {
+                ctx.notify("No checksum available, skipping verification")
+                    .await
+            }
+        }
+        let unpacked = match unpacked {
+            Some(unpacked) => unpacked,
+            None => {
+                let download_path = self.tmp_path(".download");
+                if let Some(signature) = signature {
+                    let text = self.fetch_signature(ctx, signature.suffix()).await?;
+                    signature
+                        .verify(&async_std::fs::read(&download_path).await?, &text)
+                        .context("Refusing to install the downloaded file")?;
+                    ctx.notify(&format!("Signature verified ({:?})", signature.kind))
+                        .await;
+                }
+                if let Some(openpgp) = openpgp {
+                    let text = self.fetch_signature(ctx, ".asc").await?;
+                    openpgp
+                        .verify(ctx, &async_std::fs::read(&download_path).await?, &text)
+                        .await
+                        .context("Refusing to install the downloaded file")?;
+                }
+                let download = File::open(&download_path).await?;
+                self.unpack(ctx, download, &downloaded, pick).await?
+            }
+        };
+        let installed = match unpacked {
+            Unpacked::Staged(installed) => {
+                self.unstage_tmp(&installed).await?;
+                installed
+            }
+            Unpacked::Spooled(archive, path) if self.tree.is_some() => {
+                archive
+                    .unpack_zip(&path, &self.fresh_staging().await?)
+                    .await?;
+                self.link_tree(ctx, archive, release).await?
+            }
+            Unpacked::Spooled(archive, path) => self.extract(ctx, archive, &path).await?,
+            Unpacked::Ambiguous => return Ok(None),
+            Unpacked::Tree(archive) => self.link_tree(ctx, archive, release).await?,
+        };
+        self.activate_all(ctx, &self.targets_of(&installed)).await?;
+        let dropped = self.remember(&installed);
+        for path in dropped {
+            ctx.notify(&format!(
+                "Deleting file {}, it is not installed anymore",
+                path.display()
+            ))
+            .await;
+            remove_installed(ctx, &path).await;
+        }
+        self.validators = downloaded.validators;
+        Ok(Some(true))
+    }
+
+    // Decompress and sniff the stream, executables and remembered tar entries go straight into
+    // the stage, anything needing the picker or seeking is written to a temp file
+    async fn unpack<R: Read + Unpin + Send>(
+        &self,
+        ctx: &ui::Context,
+        mut reader: R,
+        downloaded: &Downloaded,
+        pick: bool,
+    ) -> anyhow::Result<Unpacked> {
+        let head = read_head(&mut reader).await?;
+        let compression = self.guess_compression(&head, downloaded);
+        let mut decoded = compression.reader(Cursor::new(head).chain(reader));
+        let head = read_head(&mut decoded).await?;
+        let archive = self.guess_archive(&head, downloaded);
         ctx.notify(&format!(
             "Treating file as a {}{:?}{} archive with {}{:?}{} compression",
             termion::style::Bold,
@@ -38,183 +353,780 @@ impl Installer {
             termion::style::Reset,
         ))
         .await;
-        let (file, tmp_path) = self.tmp_file().await?;
-        ctx.notify(&format!("Fetching {}", &self.url)).await;
-        self.download(ctx.clone(), compression.writer(file)).await?;
-        if self.path.exists() {
-            let filename = self
-                .path
-                .as_path()
-                .file_name()
-                .expect("Install path was not a file name");
-            let mut target = data_path().await;
-            target.push(filename);
-            move_exe(&self.path, &target)
+        let decoded = Cursor::new(head).chain(decoded);
+        if self.tree.is_some() {
+            return match archive {
+                Archived::Tar => {
+                    Archive::new(decoded)
+                        .unpack(self.fresh_staging().await?)
+                        .await?;
+                    Ok(Unpacked::Tree(archive))
+                }
+                Archived::Zip => {
+                    let (mut file, path) = self.tmp_file("").await?;
+                    async_std::io::copy(decoded, &mut file).await?;
+                    file.flush().await?;
+                    Ok(Unpacked::Spooled(archive, path))
+                }
+                Archived::None => bail!("Only archives can be installed as a directory tree"),
+            };
+        }
+        // Nothing goes into the bin dir before the download is verified
+        let stage = self.tmp_stage(&self.path);
+        match (&archive, self.entry.as_deref()) {
+            (Archived::None, _) => {
+                ctx.notify(&format!("Extracting into {}", stage.display()))
+                    .await;
+                self.tmp_dir().await;
+                write_exe(decoded, &stage).await?;
+                Ok(Unpacked::Staged(Vec::new()))
+            }
+            (Archived::Tar, Some(_)) if !pick => {
+                ctx.notify(&format!("Extracting into {}", stage.display()))
+                    .await;
+                self.tmp_dir().await;
+                archive
+                    .stream_tar(ctx, decoded, &self.remembered(), |path| {
+                        self.tmp_stage(path)
+                    })
+                    .await
+            }
+            _ => {
+                let (mut file, path) = self.tmp_file("").await?;
+                async_std::io::copy(decoded, &mut file).await?;
+                file.flush().await?;
+                Ok(Unpacked::Spooled(archive, path))
+            }
+        }
+    }
+
+    // Buffered fallback, the picker needs to see the whole archive before anything is extracted
+    async fn extract(
+        &mut self,
+        ctx: &ui::Context,
+        archive: Archived,
+        src: &Path,
+    ) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let stage = stage_path(&self.path);
+        ctx.notify(&format!("Extracting into {}", stage.display()))
+            .await;
+        let kind = match archive {
+            Archived::None => {
+                move_exe(src, &stage).await?;
+                return Ok(Vec::new());
+            }
+            Archived::Tar => "Tar",
+            Archived::Zip => "Zip",
+        };
+        let files = archive.list(src).await?;
+        let patterns: Vec<&str> = self.remembered().iter().map(|(entry, _)| *entry).collect();
+        let picks = pick_entries(ctx, kind, &files, &patterns, &self.path).await?;
+        let installed = self.targets(
+            picks
+                .iter()
+                .map(|(pick, name)| (files[*pick].0.clone(), name.clone())),
+        )?;
+        self.staged = installed.iter().map(|(_, path)| path.clone()).collect();
+        let dests: Vec<(usize, PathBuf)> = picks
+            .iter()
+            .zip(&installed)
+            .map(|((pick, _), (_, path))| (*pick, stage_path(path)))
+            .collect();
+        archive.extract(src, &dests).await?;
+        Ok(installed)
+    }
+
+    // Unpack into a fresh staging dir, then pick the files to link from the unpacked tree
+    async fn link_tree(
+        &mut self,
+        ctx: &ui::Context,
+        archive: Archived,
+        release: &Release,
+    ) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let staging = self
+            .tree
+            .as_ref()
+            .expect("Not installed as a tree")
+            .staging();
+        let root = match self.tree.as_ref().and_then(|tree| tree.root.as_deref()) {
+            Some(root) => {
+                let mut dirs = read_dir(&staging).await?;
+                let mut matching = Vec::new();
+                while let Some(dir) = dirs.next().await {
+                    let name = dir?.file_name().to_string_lossy().to_string();
+                    if pattern::matches(root, &name) {
+                        matching.push(name);
+                    }
+                }
+                match matching.as_slice() {
+                    [name] => staging.join(name),
+                    _ => bail!(
+                        "{} directories of the archive match `{}`",
+                        matching.len(),
+                        root
+                    ),
+                }
+            }
+            None => staging.clone(),
+        };
+        let files = list_tree(&root).await?;
+        let patterns: Vec<&str> = self.remembered().iter().map(|(entry, _)| *entry).collect();
+        let kind = format!("{:?}", archive);
+        let picks = pick_entries(ctx, &kind, &files, &patterns, &self.path).await?;
+        let installed = self.targets(
+            picks
+                .iter()
+                .map(|(pick, name)| (files[*pick].0.clone(), name.clone())),
+        )?;
+        self.staged = installed.iter().map(|(_, path)| path.clone()).collect();
+        let version = self.tree_path(release).expect("Not installed as a tree");
+        for (entry, path) in &installed {
+            let file = root.join(entry);
+            let mode = async_std::fs::metadata(&file).await?.permissions().mode();
+            set_permissions(&file, std::fs::Permissions::from_mode(mode | 0o111)).await?;
+            link_stage(&version.join(entry), path).await?;
+        }
+        ctx.notify(&format!("Unpacking into {}", version.display()))
+            .await;
+        // The same version installed again is only deleted once the new links are in place
+        let existed = version.exists();
+        if existed {
+            let replaced = self
+                .tree
+                .as_ref()
+                .expect("Not installed as a tree")
+                .replaced(&history::key(release));
+            if replaced.exists() {
+                remove_dir_all(&replaced).await?;
+            }
+            rename(&version, &replaced)
                 .await
-                .context(target.display().to_string())?;
-            self.backup = Some(target);
+                .context(replaced.display().to_string())?;
         }
-        archive.install(ctx, &tmp_path, &self.path).await
+        rename(&root, &version)
+            .await
+            .context(version.display().to_string())?;
+        if !existed {
+            self.unpacked = Some(version);
+        }
+        Ok(installed)
     }
 
-    pub async fn revert(&mut self, ctx: &ui::Context) -> anyhow::Result<(), std::io::Error> {
-        if self.backup.is_none() {
-            ctx.notify("No backup found for this package, doing nothing")
-                .await;
+    // An empty staging dir for unpacking a tree
+    async fn fresh_staging(&self) -> anyhow::Result<PathBuf> {
+        let staging = self
+            .tree
+            .as_ref()
+            .expect("Not installed as a tree")
+            .staging();
+        if staging.exists() {
+            remove_dir_all(&staging).await?;
+        }
+        create_dir_all(&staging)
+            .await
+            .context(format!("Failed to create {}", staging.display()))?;
+        Ok(staging)
+    }
+
+    /// Where a release is unpacked if the package is installed as a tree
+    pub fn tree_path(&self, release: &Release) -> Option<PathBuf> {
+        self.tree
+            .as_ref()
+            .map(|tree| tree.dir.join(history::key(release)))
+    }
+
+    // Remembered entries and where they are installed, the main binary first
+    fn remembered(&self) -> Vec<(&str, &Path)> {
+        self.entry
+            .iter()
+            .map(|entry| (entry.as_str(), self.path.as_path()))
+            .chain(
+                self.binaries
+                    .iter()
+                    .map(|b| (b.entry.as_str(), b.path.as_path())),
+            )
+            .collect()
+    }
+
+    // The first picked entry is installed under the package name, the others under the name
+    // picked for them
+    fn targets(
+        &self,
+        entries: impl Iterator<Item = (String, Option<String>)>,
+    ) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let mut installed: Vec<(String, PathBuf)> = Vec::new();
+        for (n, (entry, name)) in entries.enumerate() {
+            let path = match (n, name) {
+                (0, _) => self.path.clone(),
+                (_, Some(name)) => self.path.with_file_name(name),
+                (_, None) => self.target(&entry)?,
+            };
+            if installed.iter().any(|(_, other)| *other == path) {
+                bail!(
+                    "More than one file would be installed as {}",
+                    path.display()
+                )
+            }
+            if n > 0 && !self.owns(&path) && path.symlink_metadata().is_ok() {
+                bail!(
+                    "Refusing to overwrite {}, it is not installed by this package",
+                    path.display()
+                )
+            }
+            installed.push((entry, path));
+        }
+        Ok(installed)
+    }
+
+    // Files installed before keep their path, new ones are named like the entry
+    fn target(&self, entry: &str) -> anyhow::Result<PathBuf> {
+        match self
+            .binaries
+            .iter()
+            .find(|b| pattern::matches(&b.entry, entry))
+        {
+            Some(binary) => Ok(binary.path.clone()),
+            None => match Path::new(entry).file_name() {
+                Some(name) => Ok(self.path.with_file_name(name)),
+                None => bail!("Archive entry {} has no file name", entry),
+            },
+        }
+    }
+
+    // Where the extracted entries go, just the main file for a plain executable
+    fn targets_of<'a>(&'a self, installed: &'a [(String, PathBuf)]) -> Vec<&'a Path> {
+        match installed.is_empty() {
+            true => vec![self.path.as_path()],
+            false => installed.iter().map(|(_, path)| path.as_path()).collect(),
+        }
+    }
+
+    // Extracted entries are kept as patterns, so updates find them again. Returns the files
+    // installed before that are not part of the package anymore
+    fn remember(&mut self, installed: &[(String, PathBuf)]) -> Vec<PathBuf> {
+        let binaries: Vec<Binary> = match installed.split_first() {
+            Some(((entry, _), binaries)) => {
+                self.entry = Some(pattern::wildcard(entry, None));
+                binaries
+                    .iter()
+                    .map(|(entry, path)| Binary {
+                        entry: pattern::wildcard(entry, None),
+                        path: path.clone(),
+                    })
+                    .collect()
+            }
+            // A plain executable, nothing is installed next to it anymore
+            None => Vec::new(),
+        };
+        let mut dropped: Vec<PathBuf> = Vec::new();
+        for path in self.binaries.iter().map(|b| &b.path).chain(&self.replaces) {
+            if *path != self.path
+                && !binaries.iter().any(|b| b.path == *path)
+                && !dropped.contains(path)
+            {
+                dropped.push(path.clone());
+            }
+        }
+        self.binaries = binaries;
+        self.replaces = Vec::new();
+        dropped
+    }
+
+    // Files this package may overwrite
+    fn owns(&self, path: &Path) -> bool {
+        self.paths().contains(&path) || self.replaces.iter().any(|other| other == path)
+    }
+
+    /// Every file installed by the package, the main binary first
+    pub fn paths(&self) -> Vec<&Path> {
+        std::iter::once(self.path.as_path())
+            .chain(self.binaries.iter().map(|b| b.path.as_path()))
+            .collect()
+    }
+
+    // Leftovers of a download that was not installed
+    async fn clean_up(&mut self, ctx: &ui::Context, failed: bool) -> anyhow::Result<()> {
+        let rollback = failed;
+        let mut failed = failed;
+        let staged = std::mem::take(&mut self.staged);
+        for path in self
+            .paths()
+            .into_iter()
+            .chain(staged.iter().map(PathBuf::as_path))
+        {
+            let stage = stage_path(path);
+            if stage.symlink_metadata().is_ok() {
+                if failed {
+                    ctx.notify("Installation failed, rolling back").await;
+                    failed = false;
+                }
+                remove_file(&stage).await?;
+            }
+            let tmp_stage = self.tmp_stage(path);
+            if tmp_stage.exists() {
+                remove_file(&tmp_stage).await?;
+            }
+        }
+        for suffix in ["", ".download"].iter() {
+            let path = self.tmp_path(suffix);
+            if path.exists() {
+                remove_file(&path).await?;
+            }
+        }
+        let unpacked = self.unpacked.take();
+        if let Some(tree) = &self.tree {
+            let staging = tree.staging();
+            if staging.exists() {
+                remove_dir_all(&staging).await?;
+            }
+            if let Some(version) = unpacked.filter(|version| rollback && version.exists()) {
+                remove_dir_all(&version).await?;
+            }
+            self.clean_up_replaced(tree, rollback).await?;
+        }
+        Ok(())
+    }
+
+    // Replaced versions are put back if the installation failed and deleted otherwise
+    async fn clean_up_replaced(&self, tree: &Tree, rollback: bool) -> anyhow::Result<()> {
+        if !tree.dir.exists() {
             return Ok(());
         }
-        let src = self.backup.as_ref().unwrap();
-        ctx.notify(&format!("From {}", src.display())).await;
-        ctx.notify(&format!("To {}", self.path.display())).await;
-        move_exe(src, &self.path).await?;
-        self.backup = None;
+        let mut keys = Vec::new();
+        let mut entries = read_dir(&tree.dir).await?;
+        while let Some(entry) = entries.next().await {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if let Some(key) = name.strip_prefix('.').and_then(|n| n.strip_suffix(".old")) {
+                keys.push(key.to_string());
+            }
+        }
+        for key in keys {
+            let replaced = tree.replaced(&key);
+            if !rollback {
+                remove_dir_all(&replaced).await?;
+                continue;
+            }
+            let version = tree.dir.join(&key);
+            if version.exists() {
+                remove_dir_all(&version).await?;
+            }
+            rename(&replaced, &version)
+                .await
+                .context(version.display().to_string())?;
+        }
         Ok(())
     }
 
-    pub async fn uninstall(&self, ctx: &ui::Context) -> anyhow::Result<(), std::io::Error> {
-        if let Some(backup) = &self.backup {
-            ctx.notify(&format!("Deleting file {}", &backup.display()))
+    /// Install the files of a release from the history store, `src` is the copy of the main file
+    /// or the version dir of a tree and `binaries` are the other files the release had
+    pub async fn restore(
+        &mut self,
+        ctx: &ui::Context,
+        src: &Path,
+        binaries: &[Binary],
+    ) -> anyhow::Result<()> {
+        let result = match self.tree {
+            Some(_) => self.restore_tree(ctx, src, binaries).await,
+            None => self.restore_files(ctx, src, binaries).await,
+        };
+        self.clean_up(ctx, result.is_err()).await?;
+        let restored = result?;
+        let dropped: Vec<PathBuf> = self
+            .binaries
+            .iter()
+            .map(|b| b.path.clone())
+            .filter(|path| *path != self.path && !restored.iter().any(|b| b.path == *path))
+            .collect();
+        for path in dropped {
+            ctx.notify(&format!(
+                "Deleting file {}, the release did not have it",
+                path.display()
+            ))
+            .await;
+            remove_installed(ctx, &path).await;
+        }
+        self.binaries = restored;
+        Ok(())
+    }
+
+    // Files of the release that can be put back, a file of someone else is never overwritten
+    async fn restorable<'a>(&self, ctx: &ui::Context, binaries: &'a [Binary]) -> Vec<&'a Binary> {
+        let mut restorable = Vec::new();
+        for binary in binaries {
+            if !self.owns(&binary.path) && binary.path.symlink_metadata().is_ok() {
+                ctx.notify(&format!(
+                    "Warning: {} is not installed by this package, not restoring it",
+                    binary.path.display()
+                ))
                 .await;
-            if let Err(err) = remove_file(backup).await {
+                continue;
+            }
+            restorable.push(binary);
+        }
+        restorable
+    }
+
+    async fn restore_files(
+        &mut self,
+        ctx: &ui::Context,
+        src: &Path,
+        binaries: &[Binary],
+    ) -> anyhow::Result<Vec<Binary>> {
+        let main = Binary {
+            entry: String::new(),
+            path: self.path.clone(),
+        };
+        let mut targets = vec![&main];
+        targets.extend(self.restorable(ctx, binaries).await);
+        self.staged = targets.iter().map(|b| b.path.clone()).collect();
+        let mut staged = Vec::new();
+        let mut restored = Vec::new();
+        for binary in targets {
+            let from = match binary.path.file_name() {
+                _ if binary.path == self.path => src.to_path_buf(),
+                Some(name) => src.with_file_name(name),
+                None => continue,
+            };
+            if !from.exists() {
                 ctx.notify(&format!(
-                    "Warning: Failed to remove file {}: {}",
-                    self.path.display(),
-                    err
+                    "Warning: {} is not in the history, keeping the installed file",
+                    binary.path.display()
                 ))
                 .await;
+                if binary.path != self.path && binary.path.exists() {
+                    restored.push(binary.clone());
+                }
+                continue;
+            }
+            copy(&from, stage_path(&binary.path))
+                .await
+                .context(format!("Failed to copy {}", from.display()))?;
+            staged.push(binary.path.as_path());
+            if binary.path != self.path {
+                restored.push(binary.clone());
             }
         }
-        ctx.notify(&format!("Deleting file {}", self.path.display()))
-            .await;
-        if let Err(err) = remove_file(&self.path).await {
-            ctx.notify(&format!(
-                "Warning: Failed to remove file {}: {}",
-                self.path.display(),
-                err
-            ))
-            .await;
+        self.activate_all(ctx, &staged).await?;
+        Ok(restored)
+    }
+
+    // Trees are kept unpacked, reverting just points the links at another version
+    async fn restore_tree(
+        &mut self,
+        ctx: &ui::Context,
+        version: &Path,
+        binaries: &[Binary],
+    ) -> anyhow::Result<Vec<Binary>> {
+        let files = list_tree(version).await?;
+        let main = self.entry.clone().map(|entry| Binary {
+            entry,
+            path: self.path.clone(),
+        });
+        let mut targets: Vec<&Binary> = main.iter().collect();
+        targets.extend(self.restorable(ctx, binaries).await);
+        self.staged = targets.iter().map(|b| b.path.clone()).collect();
+        let mut staged = Vec::new();
+        let mut restored = Vec::new();
+        for binary in targets {
+            let matching: Vec<&str> = files
+                .iter()
+                .filter(|(file, _)| pattern::matches(&binary.entry, file))
+                .map(|(file, _)| file.as_str())
+                .collect();
+            match matching.as_slice() {
+                [file] => {
+                    link_stage(&version.join(file), &binary.path).await?;
+                    staged.push(binary.path.as_path());
+                    if binary.path != self.path {
+                        restored.push(binary.clone());
+                    }
+                }
+                _ => {
+                    ctx.notify(&format!(
+                        "Warning: {} files of {} match `{}`, keeping {}",
+                        matching.len(),
+                        version.display(),
+                        binary.entry,
+                        binary.path.display()
+                    ))
+                    .await
+                }
+            }
         }
-        Ok(())
+        self.activate_all(ctx, &staged).await?;
+        Ok(restored)
     }
 
-    async fn download(
-        &self,
-        ctx: ui::Context,
-        mut body_writer: Pin<Box<dyn async_std::io::Write + Send>>,
-    ) -> anyhow::Result<()> {
-        let mut response = isahc::Request::get(&self.url)
-            .metrics(true)
-            .redirect_policy(RedirectPolicy::Limit(50))
-            .body(())
-            .context("Failed to build request body")?
-            .send_async()
-            .await
-            .context("Failed to download file")?;
-        let metrics = response.metrics().unwrap().clone();
-        let body = response.body_mut();
-        let url = self.url.to_string();
-        let progresser = smol::spawn(async move {
-            loop {
-                let progress = metrics.download_progress();
-                ctx.progress(progress.0 / 1_000, progress.1 / 1_000, &url)
+    // Either all staged files are installed or the previous ones are put back, the files being
+    // replaced are kept as a hard link until then
+    async fn activate_all(&self, ctx: &ui::Context, paths: &[&Path]) -> anyhow::Result<()> {
+        let mut activated: Vec<(&Path, bool)> = Vec::new();
+        for path in paths {
+            let previous = previous_path(path);
+            let existed = path.symlink_metadata().is_ok();
+            let result = match existed {
+                true => keep_previous(path, &previous).await,
+                false => Ok(()),
+            };
+            let result = match result {
+                Ok(()) => self.activate(ctx, path).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                if existed && previous.symlink_metadata().is_ok() {
+                    remove_file(&previous).await?;
+                }
+                ctx.notify("Installation failed, putting back the previous files")
                     .await;
-                if progress.0 == progress.1 {
-                    break;
+                for (path, existed) in activated {
+                    match existed {
+                        true => rename(previous_path(path), path).await?,
+                        false => remove_file(path).await?,
+                    }
                 }
-                Timer::after(Duration::from_millis(20)).await;
+                return Err(err);
             }
-        });
-        async_std::io::copy(body, &mut body_writer).await?;
-        body_writer.flush().await?;
-        progresser.await;
+            activated.push((path, existed));
+        }
+        for (path, existed) in activated {
+            if existed {
+                remove_file(previous_path(path)).await?;
+            }
+        }
         Ok(())
     }
 
-    fn guess_archive(&self) -> Archived {
-        if let Some(a) = &self.archive {
-            return a.clone();
-        }
-        if self.url.ends_with(".tar") {
-            return Archived::Tar;
+    async fn activate(&self, ctx: &ui::Context, path: &Path) -> anyhow::Result<()> {
+        let stage = stage_path(path);
+        let staged = File::open(&stage).await?;
+        staged.sync_all().await?;
+        if staged.metadata().await?.len() == 0 {
+            bail!("Refusing to install an empty file")
         }
-        if self.url.ends_with(".tar.gz") {
-            return Archived::Tar;
+        ctx.notify(&format!("Installing {}", path.display())).await;
+        rename(&stage, path)
+            .await
+            .context(path.display().to_string())?;
+        if let Some(dir) = path.parent() {
+            File::open(dir).await?.sync_all().await?;
         }
-        if self.url.ends_with(".tgz") {
-            return Archived::Tar;
+        Ok(())
+    }
+
+    pub async fn uninstall(&self, ctx: &ui::Context) -> anyhow::Result<(), std::io::Error> {
+        for path in self.paths() {
+            ctx.notify(&format!("Deleting file {}", path.display()))
+                .await;
+            remove_installed(ctx, path).await;
         }
-        if self.url.ends_with(".tar.bz") {
-            return Archived::Tar;
+        if let Some(tree) = self.tree.as_ref().filter(|tree| tree.dir.exists()) {
+            ctx.notify(&format!("Deleting directory {}", tree.dir.display()))
+                .await;
+            remove_dir_all(&tree.dir).await?;
         }
-        if self.url.ends_with(".tar.bz2") {
-            return Archived::Tar;
+        Ok(())
+    }
+
+    async fn fetch_signature(&self, ctx: &ui::Context, suffix: &str) -> anyhow::Result<String> {
+        let url = format!("{}{}", self.url, suffix);
+        ctx.notify(&format!("Fetching signature {}", &url)).await;
+        let mut response = self
+            .get(&url)
+            .await
+            .context("Failed to download signature")?;
+        if response.status() != 200 {
+            bail!(
+                "No signature found\nStatus: {}\nURL: {}",
+                response.status(),
+                &url
+            )
         }
-        if self.url.ends_with(".tbz") {
-            return Archived::Tar;
+        Ok(response.text().await?)
+    }
+
+    /// GET request following redirects, credentials are only sent to their own host and never
+    /// after a redirect to another scheme
+    pub async fn get(&self, url: &str) -> anyhow::Result<Response<AsyncBody>> {
+        self.get_with(url, &[]).await
+    }
+
+    async fn get_with(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Response<AsyncBody>> {
+        let mut uri: Uri = url.parse().context(url.to_string())?;
+        let scheme = uri.scheme().cloned();
+        for _ in 0..MAX_REDIRECTS {
+            let mut request = isahc::Request::get(uri.clone())
+                .metrics(true)
+                .redirect_policy(RedirectPolicy::None);
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            if let Some(credentials) = &self.credentials {
+                if uri.authority().map(|a| a.as_str()) == Some(credentials.host.as_str())
+                    && uri.scheme() == scheme.as_ref()
+                {
+                    request =
+                        request.header(credentials.header.as_str(), credentials.value.as_str());
+                }
+            }
+            let response = request
+                .body(())
+                .context("Failed to build request body")?
+                .send_async()
+                .await
+                .context(uri.to_string())?;
+            let location = match response.headers().get("Location") {
+                Some(location) if response.status().is_redirection() => location.to_str()?,
+                _ => return Ok(response),
+            };
+            uri = resolve(&uri, location).context(location.to_string())?;
         }
-        if self.url.ends_with(".tar.xz") {
-            return Archived::Tar;
+        bail!("Too many redirects: {}", url)
+    }
+
+    // Nothing is downloaded if the server answers a conditional request with 304
+    async fn request(&self, conditional: bool) -> anyhow::Result<Option<Response<AsyncBody>>> {
+        let mut headers = Vec::new();
+        if conditional {
+            if let Some(etag) = &self.validators.etag {
+                headers.push(("If-None-Match", etag.as_str()));
+            }
+            if let Some(last_modified) = &self.validators.last_modified {
+                headers.push(("If-Modified-Since", last_modified.as_str()));
+            }
         }
-        if self.url.ends_with(".txz") {
-            return Archived::Tar;
+        let response = self
+            .get_with(&self.url, &headers)
+            .await
+            .context("Failed to download file")?;
+        if response.status() == 304 && conditional {
+            return Ok(None);
         }
-        if self.url.ends_with(".zip") {
-            return Archived::Zip;
+        if !response.status().is_success() {
+            bail!("Status: {}\nURL: {}", response.status(), &self.url)
         }
-        Archived::None
+        Ok(Some(response))
     }
 
-    fn guess_compression(&self) -> Compression {
+    // Servers sending no Content-Length leave the total at 0, so it runs until `done` is set
+    fn progress(
+        &self,
+        ctx: ui::Context,
+        response: &Response<AsyncBody>,
+        done: Arc<AtomicBool>,
+    ) -> Task<()> {
+        let metrics = response.metrics().unwrap().clone();
+        let url = self.url.to_string();
+        smol::spawn(async move {
+            loop {
+                let finished = done.load(Ordering::Relaxed);
+                let progress = metrics.download_progress();
+                ctx.progress(progress.0 / 1_000, progress.1 / 1_000, &url)
+                    .await;
+                if finished {
+                    break;
+                }
+                Timer::after(Duration::from_millis(20)).await;
+            }
+        })
+    }
+    // Magic bytes first, then the response headers and the file name suffix as a last resort
+    fn guess_compression(&self, head: &[u8], downloaded: &Downloaded) -> Compression {
         if let Some(c) = &self.compression {
             return c.clone();
         }
-        if self.url.ends_with(".gz") {
-            return Compression::Gzip;
-        }
-        if self.url.ends_with(".tgz") {
-            return Compression::Gzip;
-        }
-        if self.url.ends_with(".bz") {
-            return Compression::Bzip2;
-        }
-        if self.url.ends_with(".bz2") {
-            return Compression::Bzip2;
-        }
-        if self.url.ends_with(".tbz") {
-            return Compression::Bzip2;
-        }
-        if self.url.ends_with(".xz") {
-            return Compression::Xz;
-        }
-        if self.url.ends_with(".txz") {
-            return Compression::Xz;
+        sniff::magic(head)
+            .or_else(|| {
+                downloaded
+                    .content_type
+                    .as_deref()
+                    .and_then(sniff::content_type)
+            })
+            .map(Format::compression)
+            .or_else(|| self.names(downloaded).find_map(compression_suffix))
+            .unwrap_or(Compression::None)
+    }
+
+    // Looks at the decompressed file
+    fn guess_archive(&self, head: &[u8], downloaded: &Downloaded) -> Archived {
+        if let Some(a) = &self.archive {
+            return a.clone();
         }
-        Compression::None
+        sniff::magic(head)
+            .and_then(Format::archive)
+            .or_else(|| {
+                downloaded
+                    .content_type
+                    .as_deref()
+                    .and_then(sniff::content_type)
+                    .and_then(Format::archive)
+            })
+            .or_else(|| self.names(downloaded).find_map(archive_suffix))
+            .unwrap_or(Archived::None)
     }
 
-    async fn tmp_file(&self) -> anyhow::Result<(File, PathBuf)> {
-        let file_name = self
-            .path
-            .file_name()
-            .unwrap_or_else(|| panic!("Invalid filename: {}", self.path.display()));
-        let mut tmp_path = std::env::temp_dir();
-        tmp_path.push("blindspot");
-        if !tmp_path.exists() {
-            create_dir(&tmp_path)
+    fn names<'a>(&'a self, downloaded: &'a Downloaded) -> impl Iterator<Item = &'a str> {
+        downloaded
+            .file_name
+            .as_deref()
+            .into_iter()
+            .chain(Some(sniff::url_path(&self.url)))
+    }
+
+    async fn tmp_dir(&self) {
+        if let Some(dir) = self.tmp_path("").parent().filter(|dir| !dir.exists()) {
+            create_dir(dir)
                 .await
-                .unwrap_or_else(|_| panic!("Failed to create tmp dir: {}", &tmp_path.display()))
+                .unwrap_or_else(|_| panic!("Failed to create tmp dir: {}", dir.display()))
         }
-        tmp_path.push(file_name);
+    }
+
+    async fn tmp_file(&self, suffix: &str) -> anyhow::Result<(File, PathBuf)> {
+        let tmp_path = self.tmp_path(suffix);
+        self.tmp_dir().await;
         Ok((
             OpenOptions::new()
                 .create(true)
                 .write(true)
+                .truncate(true)
                 .open(&tmp_path)
                 .await
                 .context("failed to create tmp file")?,
             tmp_path,
         ))
     }
+
+    // Extracted files wait here until the download is verified
+    fn tmp_stage(&self, path: &Path) -> PathBuf {
+        let filename = path
+            .file_name()
+            .unwrap_or_else(|| panic!("Invalid filename: {}", path.display()));
+        self.tmp_path(&format!(".{}.stage", filename.to_string_lossy()))
+    }
+
+    // Move the verified files from the temp dir next to their targets
+    async fn unstage_tmp(&self, installed: &[(String, PathBuf)]) -> anyhow::Result<()> {
+        for path in self.targets_of(installed) {
+            move_exe(&self.tmp_stage(path), &stage_path(path))
+                .await
+                .context(format!("Failed to stage {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn tmp_path(&self, suffix: &str) -> PathBuf {
+        let file_name = self
+            .path
+            .file_name()
+            .unwrap_or_else(|| panic!("Invalid filename: {}", self.path.display()));
+        let mut tmp_path = std::env::temp_dir();
+        tmp_path.push("blindspot");
+        tmp_path.push(format!("{}{}", file_name.to_string_lossy(), suffix));
+        tmp_path
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -225,57 +1137,157 @@ pub enum Archived {
 }
 
 impl Archived {
-    async fn install(&self, ctx: &ui::Context, src: &Path, dest: &Path) -> anyhow::Result<()> {
-        ctx.notify(&format!("Installing into {}", dest.display()))
-            .await;
-        match &self {
-            Archived::None => move_exe(&src.to_path_buf(), dest).await?,
-            Archived::Tar => self.install_tar(ctx, src, dest).await?,
-            Archived::Zip => self.install_tar(ctx, src, dest).await?,
+    // Paths and sizes of the files in the archive
+    async fn list(&self, src: &Path) -> anyhow::Result<Vec<(String, u64)>> {
+        let mut files = Vec::new();
+        match self {
+            Archived::None => {}
+            Archived::Tar => {
+                let mut entries = Archive::new(File::open(src).await?).entries()?;
+                while let Some(file) = entries.next().await {
+                    let f = file?;
+                    files.push((f.header().path()?.display().to_string(), f.header().size()?));
+                }
+            }
+            Archived::Zip => {
+                let archive = open_zip(src).await?;
+                for entry in archive.file().entries() {
+                    files.push((
+                        entry.filename().as_str()?.to_string(),
+                        entry.uncompressed_size(),
+                    ));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    // Write the files at the given indices of `list` to their destinations
+    async fn extract(&self, src: &Path, dests: &[(usize, PathBuf)]) -> anyhow::Result<()> {
+        match self {
+            Archived::None => {}
+            Archived::Tar => {
+                let mut file_index = 0;
+                let mut e = Archive::new(File::open(src).await?).entries()?;
+                while let Some(file) = e.next().await {
+                    let f = file?;
+                    if let Some((_, dest)) = dests.iter().find(|(i, _)| *i == file_index) {
+                        write_exe(f, dest).await?;
+                    }
+                    file_index += 1;
+                }
+            }
+            Archived::Zip => {
+                let mut archive = open_zip(src).await?;
+                for (pick, dest) in dests {
+                    let mode = zip_mode(&archive.file().entries()[*pick], 0o750, 0o700);
+                    let mut target_file = OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .truncate(true)
+                        .mode(mode)
+                        .open(dest)
+                        .await?;
+                    let mut entry = archive
+                        .reader_without_entry(*pick)
+                        .await
+                        .context("Failed to read Zip entry")?;
+                    async_std::io::copy(&mut entry, &mut target_file).await?;
+                    set_permissions(dest, std::fs::Permissions::from_mode(mode)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Unpack every file of a Zip archive into `dest`
+    async fn unpack_zip(&self, src: &Path, dest: &Path) -> anyhow::Result<()> {
+        let mut archive = open_zip(src).await?;
+        for i in 0..archive.file().entries().len() {
+            let entry = &archive.file().entries()[i];
+            let name = entry.filename().as_str()?.to_string();
+            let dir = entry.dir()?;
+            let mode = zip_mode(entry, 0o644, 0o600);
+            let relative = Path::new(&name);
+            if !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                bail!("Refusing to unpack {} outside of {}", name, dest.display())
+            }
+            let path = dest.join(relative);
+            if dir {
+                create_dir_all(&path).await?;
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).await?;
+            }
+            let mut target_file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .mode(mode)
+                .open(&path)
+                .await?;
+            let mut reader = archive
+                .reader_without_entry(i)
+                .await
+                .context("Failed to read Zip entry")?;
+            async_std::io::copy(&mut reader, &mut target_file).await?;
+            set_permissions(&path, std::fs::Permissions::from_mode(mode)).await?;
         }
         Ok(())
     }
 
-    async fn install_tar(&self, ctx: &ui::Context, src: &Path, dest: &Path) -> anyhow::Result<()> {
-        ctx.notify("Choose a file from Tar archive...").await;
-        let mut file_index = 0;
-        let archive = Archive::new(async_std::fs::File::open(src).await?);
-        let mut entries = archive.entries()?;
+    // Remembered entries are extracted while iterating, the archive never touches the disk
+    async fn stream_tar<R: Read + Unpin + Send>(
+        &self,
+        ctx: &ui::Context,
+        src: R,
+        targets: &[(&str, &Path)],
+        stage: impl Fn(&Path) -> PathBuf,
+    ) -> anyhow::Result<Unpacked> {
+        let mut matching: Vec<Vec<String>> = vec![Vec::new(); targets.len()];
+        let mut entries = Archive::new(src).entries()?;
         while let Some(file) = entries.next().await {
             let f = file?;
+            let path = f.header().path()?.display().to_string();
+            let mut fresh = Vec::new();
+            for (i, (entry, _)) in targets.iter().enumerate() {
+                if pattern::matches(entry, &path) {
+                    matching[i].push(path.clone());
+                    if matching[i].len() == 1 {
+                        fresh.push(i);
+                    }
+                }
+            }
+            // An entry can only be read once, more targets for it get a copy
+            if let Some((first, rest)) = fresh.split_first() {
+                let first = stage(targets[*first].1);
+                write_exe(f, &first).await?;
+                for i in rest {
+                    copy(&first, stage(targets[*i].1)).await?;
+                }
+            }
+        }
+        if let Some(i) = (0..targets.len()).find(|i| matching[*i].len() != 1) {
             ctx.notify(&format!(
-                "{}-> {}{}\t{:.2}mb\t{}",
-                termion::style::Bold,
-                file_index,
-                termion::style::Reset,
-                f.header().size()? as f32 / 1_000_000.0,
-                f.header().path()?.display()
+                "{} files match `{}`, downloading again to choose",
+                matching[i].len(),
+                targets[i].0
             ))
             .await;
-            file_index += 1;
+            return Ok(Unpacked::Ambiguous);
         }
-        let pick = ctx
-            .ask_number(0, file_index, "Enter the file number to install:")
-            .await?;
-        file_index = 0;
-        let mut e = Archive::new(async_std::fs::File::open(src).await?).entries()?;
-        while let Some(file) = e.next().await {
-            let mut f = file?;
-            if pick == file_index {
-                ctx.notify(&format!("Installing {}", &dest.display())).await;
-                let mut target_file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .truncate(true)
-                    .mode(0o750)
-                    .open(dest)
-                    .await?;
-                async_std::io::copy(&mut f, &mut target_file).await?;
-                break;
-            }
-            file_index += 1;
+        let mut installed = Vec::new();
+        for ((entry, path), mut matched) in targets.iter().zip(matching) {
+            let file = matched.remove(0);
+            ctx.notify(&format!("Using file {} (matches `{}`)", file, entry))
+                .await;
+            installed.push((file, path.to_path_buf()));
         }
-        Ok(())
+        Ok(Unpacked::Staged(installed))
     }
 
     pub fn variants() -> [&'static str; 3] {
@@ -295,26 +1307,36 @@ impl std::str::FromStr for Archived {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Compression {
     None,
     Gzip,
     Bzip2,
     Xz,
+    Zstd,
+    Lz4,
+    Brotli,
 }
 
 impl Compression {
-    fn writer(&self, file: File) -> Pin<Box<dyn async_std::io::Write + Send>> {
+    fn reader<'a, R: Read + Unpin + Send + 'a>(
+        &self,
+        reader: R,
+    ) -> Box<dyn Read + Unpin + Send + 'a> {
+        let reader = BufReader::new(reader);
         match self {
-            Compression::None => Box::pin(file),
-            Compression::Gzip => Box::pin(GzipDecoder::new(file)),
-            Compression::Bzip2 => Box::pin(BzDecoder::new(file)),
-            Compression::Xz => Box::pin(XzDecoder::new(file)),
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(GzipDecoder::new(reader)),
+            Compression::Bzip2 => Box::new(BzDecoder::new(reader)),
+            Compression::Xz => Box::new(XzDecoder::new(reader)),
+            Compression::Zstd => Box::new(ZstdDecoder::new(reader)),
+            Compression::Lz4 => Box::new(Lz4Decoder::new(reader)),
+            Compression::Brotli => Box::new(BrotliDecoder::new(reader)),
         }
     }
 
-    pub fn variants() -> [&'static str; 4] {
-        ["gzip", "bzip2", "xz", "none"]
+    pub fn variants() -> [&'static str; 7] {
+        ["gzip", "bzip2", "xz", "zstd", "lz4", "brotli", "none"]
     }
 }
 
@@ -323,14 +1345,333 @@ impl std::str::FromStr for Compression {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "gzip" => Ok(Compression::Gzip),
-            "bzip2" => Ok(Compression::Gzip),
-            "xz" => Ok(Compression::Gzip),
+            "bzip2" => Ok(Compression::Bzip2),
+            "xz" => Ok(Compression::Xz),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            "brotli" => Ok(Compression::Brotli),
             "none" => Ok(Compression::None),
             _ => Err(format!("Invalid compression: {}", s)),
         }
     }
 }
 
+fn archive_suffix(name: &str) -> Option<Archived> {
+    if name.ends_with(".tar") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tar.gz") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tgz") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tar.bz") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tar.bz2") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tbz") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tar.xz") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".txz") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tar.zst") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tzst") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tar.lz4") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".tar.br") {
+        return Some(Archived::Tar);
+    }
+    if name.ends_with(".zip") {
+        return Some(Archived::Zip);
+    }
+    None
+}
+
+fn compression_suffix(name: &str) -> Option<Compression> {
+    if name.ends_with(".gz") {
+        return Some(Compression::Gzip);
+    }
+    if name.ends_with(".tgz") {
+        return Some(Compression::Gzip);
+    }
+    if name.ends_with(".bz") {
+        return Some(Compression::Bzip2);
+    }
+    if name.ends_with(".bz2") {
+        return Some(Compression::Bzip2);
+    }
+    if name.ends_with(".tbz") {
+        return Some(Compression::Bzip2);
+    }
+    if name.ends_with(".xz") {
+        return Some(Compression::Xz);
+    }
+    if name.ends_with(".txz") {
+        return Some(Compression::Xz);
+    }
+    if name.ends_with(".zst") {
+        return Some(Compression::Zstd);
+    }
+    if name.ends_with(".tzst") {
+        return Some(Compression::Zstd);
+    }
+    if name.ends_with(".lz4") {
+        return Some(Compression::Lz4);
+    }
+    if name.ends_with(".br") {
+        return Some(Compression::Brotli);
+    }
+    None
+}
+
+// Enough for the magic bytes of all known formats, including the tar header
+async fn read_head<R: Read + Unpin>(reader: &mut R) -> anyhow::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(512);
+    reader.take(512).read_to_end(&mut head).await?;
+    Ok(head)
+}
+
+// The first pick is the main binary, the others are installed next to it under their own or
+// the picked name
+async fn pick_entries(
+    ctx: &ui::Context,
+    kind: &str,
+    files: &[(String, u64)],
+    patterns: &[&str],
+    dest: &Path,
+) -> anyhow::Result<Vec<(usize, Option<String>)>> {
+    let mut picks = Vec::new();
+    for pattern in patterns {
+        let matching: Vec<usize> = (0..files.len())
+            .filter(|i| pattern::matches(pattern, &files[*i].0))
+            .collect();
+        if matching.len() != 1 {
+            ctx.notify(&format!(
+                "{} files match `{}`, please choose again",
+                matching.len(),
+                pattern
+            ))
+            .await;
+            break;
+        }
+        picks.push(matching[0]);
+    }
+    if !patterns.is_empty() && picks.len() == patterns.len() {
+        for (pick, pattern) in picks.iter().zip(patterns) {
+            ctx.notify(&format!(
+                "Using file {} (matches `{}`)",
+                files[*pick].0, pattern
+            ))
+            .await;
+        }
+        return Ok(picks.into_iter().map(|pick| (pick, None)).collect());
+    }
+    ctx.notify(&format!("Choose files from {} archive...", kind))
+        .await;
+    for (i, (path, size)) in files.iter().enumerate() {
+        ctx.notify(&format!(
+            "{}-> {}{}\t{:.2}mb\t{}",
+            termion::style::Bold,
+            i,
+            termion::style::Reset,
+            *size as f32 / 1_000_000.0,
+            path
+        ))
+        .await;
+    }
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    loop {
+        let picks = ctx
+            .ask_picks(
+                0,
+                files.len(),
+                default_entry(files, dest),
+                &format!(
+                    "Enter the numbers of the files to install, the first one becomes `{}`, add `=<name>` to name the others:",
+                    name
+                ),
+            )
+            .await?;
+        match picks.first() {
+            Some((_, Some(_))) => {
+                ctx.notify(&format!("The first file is always installed as `{}`", name))
+                    .await
+            }
+            _ => return Ok(picks),
+        }
+    }
+}
+
+// Either the only file in the archive or the only file named like the package
+fn default_entry(files: &[(String, u64)], dest: &Path) -> Option<usize> {
+    let candidates: Vec<usize> = (0..files.len())
+        .filter(|i| files[*i].1 > 0 && !files[*i].0.ends_with('/'))
+        .collect();
+    if candidates.len() == 1 {
+        return Some(candidates[0]);
+    }
+    let named: Vec<usize> = candidates
+        .into_iter()
+        .filter(|i| Path::new(&files[*i].0).file_name() == dest.file_name())
+        .collect();
+    match named.as_slice() {
+        [i] => Some(*i),
+        _ => None,
+    }
+}
+
+// Target of a redirect, relative locations are resolved against the path of the request
+fn resolve(base: &Uri, location: &str) -> anyhow::Result<Uri> {
+    if let Ok(next) = location.parse::<Uri>() {
+        if next.scheme().is_some() {
+            return Ok(next);
+        }
+    }
+    let scheme = base.scheme_str().unwrap_or("https");
+    if location.starts_with("//") {
+        return Ok(format!("{}:{}", scheme, location).parse()?);
+    }
+    let (path, query) = match location.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (location, None),
+    };
+    let joined = match path {
+        "" => base.path().to_string(),
+        _ if path.starts_with('/') => path.to_string(),
+        _ => format!(
+            "{}{}",
+            &base.path()[..=base.path().rfind('/').unwrap_or(0)],
+            path
+        ),
+    };
+    // Remove `.` and `..` segments, a trailing one keeps the slash
+    let mut segments: Vec<&str> = Vec::new();
+    let parts: Vec<&str> = joined.split('/').skip(1).collect();
+    for (i, segment) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+        match *segment {
+            "." | ".." => {
+                if *segment == ".." {
+                    segments.pop();
+                }
+                if last {
+                    segments.push("");
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+    let query = match (query, path) {
+        (Some(query), _) => format!("?{}", query),
+        (None, "") => base.query().map_or(String::new(), |q| format!("?{}", q)),
+        (None, _) => String::new(),
+    };
+    Ok(format!(
+        "{}://{}/{}{}",
+        scheme,
+        base.authority().map_or("", |a| a.as_str()),
+        segments.join("/"),
+        query
+    )
+    .parse()?)
+}
+
+// Files and symlinks below `dir` with their sizes, relative to `dir`
+async fn list_tree(dir: &Path) -> anyhow::Result<Vec<(String, u64)>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(next) = dirs.pop() {
+        let mut entries = read_dir(&next).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let path: PathBuf = entry.path().into();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let size = async_std::fs::metadata(&path).await.map_or(0, |m| m.len());
+            files.push((path.strip_prefix(dir)?.display().to_string(), size));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Failing to delete an installed file is not worth failing the whole operation
+async fn remove_installed(ctx: &ui::Context, path: &Path) {
+    if let Err(err) = remove_file(path).await {
+        ctx.notify(&format!(
+            "Warning: Failed to remove file {}: {}",
+            path.display(),
+            err
+        ))
+        .await;
+    }
+}
+
+// Links into a tree are staged like copies, a stale link may be dangling
+async fn link_stage(file: &Path, path: &Path) -> anyhow::Result<()> {
+    let stage = stage_path(path);
+    if stage.symlink_metadata().is_ok() {
+        remove_file(&stage).await?;
+    }
+    symlink(file, &stage)
+        .await
+        .context(format!("Failed to link {}", stage.display()))?;
+    Ok(())
+}
+
+// The installed file stays reachable under this name until all new files are in place
+fn previous_path(path: &Path) -> PathBuf {
+    let filename = path
+        .file_name()
+        .unwrap_or_else(|| panic!("Invalid filename: {}", path.display()));
+    path.with_file_name(format!(".{}.previous", filename.to_string_lossy()))
+}
+
+async fn keep_previous(path: &Path, previous: &Path) -> anyhow::Result<()> {
+    if previous.symlink_metadata().is_ok() {
+        remove_file(previous).await?;
+    }
+    hard_link(path, previous)
+        .await
+        .context(format!("Failed to keep {}", path.display()))
+}
+
+// Staging happens in the same directory to make the final rename atomic
+fn stage_path(path: &Path) -> PathBuf {
+    let filename = path
+        .file_name()
+        .unwrap_or_else(|| panic!("Invalid filename: {}", path.display()));
+    path.with_file_name(format!(".{}.blindspot", filename.to_string_lossy()))
+}
+
+async fn write_exe<R: Read + Unpin>(mut src: R, dest: &Path) -> anyhow::Result<()> {
+    let mut target_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o750)
+        .open(dest)
+        .await?;
+    async_std::io::copy(&mut src, &mut target_file).await?;
+    target_file.flush().await?;
+    set_permissions(dest, std::fs::Permissions::from_mode(0o750)).await?;
+    Ok(())
+}
+
 async fn move_exe(src: &Path, dest: &Path) -> anyhow::Result<(), std::io::Error> {
     copy(src, dest).await?;
     remove_file(src).await?;
@@ -339,3 +1680,383 @@ async fn move_exe(src: &Path, dest: &Path) -> anyhow::Result<(), std::io::Error>
     perm.set_mode(0o750);
     set_permissions(dest, perm).await
 }
+
+async fn open_zip(src: &Path) -> anyhow::Result<ZipFileReader<BufReader<File>>> {
+    ZipFileReader::new(BufReader::new(File::open(src).await?))
+        .await
+        .context("Failed to read Zip archive")
+}
+
+// Keep the permissions from the central directory but make sure the owner has at least `owner`,
+// `default` if the archive has none
+fn zip_mode(entry: &ZipEntry, default: u32, owner: u32) -> u32 {
+    entry
+        .unix_permissions()
+        .map_or(default, |mode| u32::from(mode) & 0o777 | owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::futures::write::{
+        BrotliEncoder, BzEncoder, GzipEncoder, Lz4Encoder, XzEncoder, ZstdEncoder,
+    };
+
+    use super::*;
+
+    async fn encode<W: async_std::io::Write + Unpin>(mut encoder: W, data: &[u8]) -> W {
+        encoder.write_all(data).await.unwrap();
+        smol::io::AsyncWriteExt::close(&mut encoder).await.unwrap();
+        encoder
+    }
+
+    async fn compress(compression: &Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::None => data.to_vec(),
+            Compression::Gzip => encode(GzipEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            Compression::Bzip2 => encode(BzEncoder::new(Vec::new()), data).await.into_inner(),
+            Compression::Xz => encode(XzEncoder::new(Vec::new()), data).await.into_inner(),
+            Compression::Zstd => encode(ZstdEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+            Compression::Lz4 => encode(Lz4Encoder::new(Vec::new()), data).await.into_inner(),
+            Compression::Brotli => encode(BrotliEncoder::new(Vec::new()), data)
+                .await
+                .into_inner(),
+        }
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let data: Vec<u8> = (0..200_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect();
+        smol::block_on(async {
+            for name in Compression::variants() {
+                let compression: Compression = name.parse().unwrap();
+                let compressed = compress(&compression, &data).await;
+                let mut decoded = Vec::new();
+                compression
+                    .reader(Cursor::new(compressed))
+                    .read_to_end(&mut decoded)
+                    .await
+                    .unwrap();
+                assert!(decoded == data, "{} did not round-trip", name);
+            }
+        })
+    }
+
+    #[test]
+    fn redirect_locations() {
+        let base: Uri = "https://example.com/releases/v1/tool.tar.gz?x=1"
+            .parse()
+            .unwrap();
+        for (location, expected) in [
+            ("http://other.org/a", "http://other.org/a"),
+            ("//cdn.example.com/b", "https://cdn.example.com/b"),
+            (
+                "/download/tool.tar.gz",
+                "https://example.com/download/tool.tar.gz",
+            ),
+            (
+                "tool-1.0.tar.gz",
+                "https://example.com/releases/v1/tool-1.0.tar.gz",
+            ),
+            (
+                "../v2/tool.tar.gz?y=2",
+                "https://example.com/releases/v2/tool.tar.gz?y=2",
+            ),
+            ("./a/../b", "https://example.com/releases/v1/b"),
+            ("../../../../x", "https://example.com/x"),
+            ("..", "https://example.com/releases/"),
+        ] {
+            assert_eq!(resolve(&base, location).unwrap().to_string(), expected);
+        }
+    }
+
+    fn installer(path: PathBuf) -> Installer {
+        Installer {
+            url: String::new(),
+            path,
+            compression: None,
+            archive: None,
+            backup: None,
+            entry: None,
+            binaries: Vec::new(),
+            tree: None,
+            checksum: None,
+            validators: Validators::default(),
+            credentials: None,
+            replaces: Vec::new(),
+            staged: Vec::new(),
+            unpacked: None,
+        }
+    }
+
+    #[test]
+    fn foreign_targets() {
+        let dir = std::env::temp_dir().join("blindspot-test-targets");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("ld.tool"), "").unwrap();
+        let mut installer = installer(dir.join("tool"));
+        let entries = || {
+            ["bin/tool", "bin/ld.tool"]
+                .iter()
+                .map(|entry| (entry.to_string(), None))
+        };
+        assert!(installer.targets(entries()).is_err());
+        installer.replaces = vec![dir.join("ld.tool")];
+        let installed = installer.targets(entries()).unwrap();
+        assert_eq!(installed[1].1, dir.join("ld.tool"));
+        assert!(installer.remember(&installed).is_empty());
+        let renamed = installer
+            .targets(entries().map(|(entry, _)| (entry, Some("ld".to_string()))))
+            .unwrap();
+        assert_eq!(renamed[0].1, dir.join("tool"));
+        assert_eq!(renamed[1].1, dir.join("ld"));
+        assert_eq!(
+            installer.remember(&installed[..1]),
+            vec![dir.join("ld.tool")]
+        );
+        let parent = ["bin/tool", "bin/foo/.."]
+            .iter()
+            .map(|entry| (entry.to_string(), None));
+        assert!(installer.targets(parent).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn activation_rolls_back() {
+        let dir = std::env::temp_dir().join("blindspot-test-activation");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (tool, ld) = (dir.join("tool"), dir.join("ld.tool"));
+        std::fs::write(&tool, "old").unwrap();
+        std::fs::write(stage_path(&tool), "new").unwrap();
+        let installer = installer(tool.clone());
+        smol::block_on(async {
+            let ctx = ui::context("🧪", "test").await;
+            // Nothing was staged for the second file
+            assert!(installer.activate_all(&ctx, &[&tool, &ld]).await.is_err());
+            assert_eq!(std::fs::read_to_string(&tool).unwrap(), "old");
+            assert!(!ld.exists());
+            std::fs::write(stage_path(&tool), "new").unwrap();
+            std::fs::write(stage_path(&ld), "new ld").unwrap();
+            installer.activate_all(&ctx, &[&tool, &ld]).await.unwrap();
+            assert_eq!(std::fs::read_to_string(&tool).unwrap(), "new");
+            assert_eq!(std::fs::read_to_string(&ld).unwrap(), "new ld");
+        });
+        assert!(!previous_path(&tool).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore_binaries() {
+        let dir = std::env::temp_dir().join("blindspot-test-restore");
+        let (bin, v1, v2) = (dir.join("bin"), dir.join("1.0.0"), dir.join("2.0.0"));
+        for (path, text) in [
+            (bin.join("tool"), "2"),
+            (v1.join("tool"), "1"),
+            (v1.join("ld.tool"), "1 ld"),
+            (v2.join("tool"), "2"),
+        ] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        let ld = Binary {
+            entry: "bin/ld.tool".to_string(),
+            path: bin.join("ld.tool"),
+        };
+        let mut installer = installer(bin.join("tool"));
+        smol::block_on(async {
+            let ctx = ui::context("🧪", "test").await;
+            installer
+                .restore(&ctx, &v1.join("tool"), std::slice::from_ref(&ld))
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read_to_string(bin.join("tool")).unwrap(), "1");
+            assert_eq!(std::fs::read_to_string(&ld.path).unwrap(), "1 ld");
+            assert_eq!(installer.binaries.len(), 1);
+            installer
+                .restore(&ctx, &v2.join("tool"), &[])
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read_to_string(bin.join("tool")).unwrap(), "2");
+            assert!(!ld.path.exists());
+            assert!(installer.binaries.is_empty());
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    async fn tar_gz(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = async_tar::Builder::new(Vec::new());
+        for (path, text) in files {
+            let mut header = async_tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, text.as_bytes())
+                .await
+                .unwrap();
+        }
+        compress(&Compression::Gzip, &builder.into_inner().await.unwrap()).await
+    }
+
+    #[test]
+    fn stream_remembered_entries() {
+        let dir = std::env::temp_dir().join("blindspot-test-stream");
+        let downloaded = Downloaded {
+            validators: Validators::default(),
+            content_type: None,
+            file_name: None,
+        };
+        smol::block_on(async {
+            let ctx = ui::context("🧪", "test").await;
+            let archive = tar_gz(&[
+                ("tool-1.0/bin/tool", "tool"),
+                ("tool-1.0/bin/ld.tool", "ld"),
+                ("tool-1.0/README", "readme"),
+            ])
+            .await;
+            let mut installer = installer(dir.join("streamtool"));
+            let unpack = |installer: Installer, pick| {
+                let (ctx, archive, downloaded) = (&ctx, &archive, &downloaded);
+                async move {
+                    installer
+                        .unpack(ctx, Cursor::new(archive.clone()), downloaded, pick)
+                        .await
+                        .unwrap()
+                }
+            };
+            installer.entry = Some("tool-*/bin/tool".to_string());
+            match unpack(installer.clone(), false).await {
+                Unpacked::Staged(installed) => {
+                    assert_eq!(
+                        installed,
+                        [("tool-1.0/bin/tool".to_string(), installer.path.clone())]
+                    )
+                }
+                _ => panic!("expected the entry to be staged"),
+            }
+            let stage = installer.tmp_stage(&installer.path);
+            assert_eq!(std::fs::read_to_string(&stage).unwrap(), "tool");
+            // Two targets of the same entry both get a copy
+            installer.binaries = vec![Binary {
+                entry: "tool-*/bin/tool".to_string(),
+                path: dir.join("tool-copy"),
+            }];
+            match unpack(installer.clone(), false).await {
+                Unpacked::Staged(installed) => assert_eq!(installed.len(), 2),
+                _ => panic!("expected both targets to be staged"),
+            }
+            let copy = installer.tmp_stage(&dir.join("tool-copy"));
+            assert_eq!(std::fs::read_to_string(&copy).unwrap(), "tool");
+            installer.binaries = Vec::new();
+            for entry in ["tool-*/lib/tool", "tool-*/bin/*"] {
+                installer.entry = Some(entry.to_string());
+                assert!(
+                    matches!(unpack(installer.clone(), false).await, Unpacked::Ambiguous),
+                    "{}",
+                    entry
+                );
+            }
+            match unpack(installer.clone(), true).await {
+                Unpacked::Spooled(Archived::Tar, path) => std::fs::remove_file(path).unwrap(),
+                _ => panic!("expected the picker to get the archive"),
+            }
+            for path in [stage, copy] {
+                std::fs::remove_file(path).unwrap();
+            }
+        });
+    }
+
+    async fn zip(files: &[(&str, &str, u16)]) -> Vec<u8> {
+        let mut writer = async_zip::base::write::ZipFileWriter::new(Vec::new());
+        for (path, content, mode) in files {
+            let entry = async_zip::ZipEntryBuilder::new(
+                path.to_string().into(),
+                async_zip::Compression::Deflate,
+            )
+            .unix_permissions(*mode);
+            writer
+                .write_entry_whole(entry, content.as_bytes())
+                .await
+                .unwrap();
+        }
+        writer.close().await.unwrap()
+    }
+
+    #[test]
+    fn zip_entries() {
+        let dir = std::env::temp_dir().join("blindspot-test-zip");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        smol::block_on(async {
+            let src = dir.join("tool.zip");
+            let archive = zip(&[
+                ("tool/bin/tool", "tool", 0o755),
+                ("tool/README", "readme", 0o444),
+                ("tool/doc.txt", "doc", 0),
+            ])
+            .await;
+            std::fs::write(&src, archive).unwrap();
+            assert_eq!(
+                Archived::Zip.list(&src).await.unwrap(),
+                [
+                    ("tool/bin/tool".to_string(), 4),
+                    ("tool/README".to_string(), 6),
+                    ("tool/doc.txt".to_string(), 3)
+                ]
+            );
+            // Picked files can always be executed by their owner
+            let dests = [
+                (0, dir.join("tool")),
+                (1, dir.join("readme")),
+                (2, dir.join("doc")),
+            ];
+            Archived::Zip.extract(&src, &dests).await.unwrap();
+            for ((_, dest), (content, expected)) in
+                dests
+                    .iter()
+                    .zip([("tool", 0o755), ("readme", 0o744), ("doc", 0o700)])
+            {
+                assert_eq!(std::fs::read_to_string(dest).unwrap(), content);
+                assert_eq!(mode(dest), expected, "{}", dest.display());
+            }
+            // Unpacked trees keep the modes, only making sure the owner can read and write
+            let tree = dir.join("tree");
+            Archived::Zip.unpack_zip(&src, &tree).await.unwrap();
+            for (path, content, expected) in [
+                ("tool/bin/tool", "tool", 0o755),
+                ("tool/README", "readme", 0o644),
+                ("tool/doc.txt", "doc", 0o600),
+            ] {
+                assert_eq!(std::fs::read_to_string(tree.join(path)).unwrap(), content);
+                assert_eq!(mode(&tree.join(path)), expected, "{}", path);
+            }
+            let evil = dir.join("evil.zip");
+            std::fs::write(&evil, zip(&[("../evil", "evil", 0o644)]).await).unwrap();
+            assert!(Archived::Zip.unpack_zip(&evil, &tree).await.is_err());
+            assert!(!dir.join("evil").exists());
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compression_from_str() {
+        for (name, compression) in [
+            ("gzip", Compression::Gzip),
+            ("bzip2", Compression::Bzip2),
+            ("xz", Compression::Xz),
+            ("zstd", Compression::Zstd),
+            ("lz4", Compression::Lz4),
+            ("brotli", Compression::Brotli),
+            ("none", Compression::None),
+        ] {
+            assert_eq!(name.parse::<Compression>(), Ok(compression));
+        }
+        assert!("zip".parse::<Compression>().is_err());
+    }
+}
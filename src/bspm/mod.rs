@@ -1,23 +1,211 @@
 use std::{
+    collections::BTreeMap,
     env,
     path::{Path, PathBuf},
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use async_std::fs::{create_dir, File};
 use async_std::prelude::*;
 
+mod api;
+
+mod discover;
+
+mod gitea;
+
+mod github;
+
+mod gitlab;
+
+mod history;
+
 mod package;
-use package::Package;
+pub use package::Channel;
+use package::{Package, Release};
 
 pub mod installer;
-use installer::Installer;
+use installer::{Installer, Tree};
+
+pub mod openpgp;
+use openpgp::OpenPgp;
+
+mod pattern;
+
+mod platform;
+
+mod sniff;
+
+mod source;
+use source::PackageSource;
+
+mod template;
+
+mod version;
+
+pub mod verify;
+use verify::Signature;
 
 pub mod ui;
 use ui::context;
 
+/// Package settings given on the command line at installation
+#[derive(Default, Debug)]
+pub struct InstallOptions {
+    pub force: bool,
+    pub compression: Option<installer::Compression>,
+    pub archive: Option<installer::Archived>,
+    pub auto: bool,
+    pub signature: Option<Signature>,
+    pub openpgp: Option<OpenPgp>,
+    pub tag: Option<String>,
+    pub constraint: Option<String>,
+    pub channel: Channel,
+    /// Repository the versions of a download URL are taken from
+    pub versions: Option<String>,
+    /// Web page or JSON API the versions of a download URL are read from
+    pub versions_url: Option<String>,
+    pub versions_json: Option<String>,
+    pub versions_regex: Option<String>,
+    /// Unpack the whole archive instead of single files
+    pub tree: bool,
+    /// Top-level directory of the archive to unpack
+    pub tree_root: Option<String>,
+}
+
+/// Global settings, packages may override them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Number of installed releases kept per package for reverting
+    pub keep_versions: usize,
+    /// GitHub API token, the `GITHUB_TOKEN` and `GH_TOKEN` env vars take precedence
+    pub github_token: Option<String>,
+    /// API settings of other hosts, like GitHub Enterprise servers
+    pub hosts: BTreeMap<String, Host>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            keep_versions: 3,
+            github_token: None,
+            hosts: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Kind of server running on a host, configured or guessed from the host name
+    pub fn forge(&self, host: &str) -> Forge {
+        match self.hosts.get(host).and_then(|h| h.kind) {
+            Some(kind) => kind,
+            None if host == GITLAB || host.starts_with("gitlab.") => Forge::GitLab,
+            None if host == CODEBERG
+                || host.starts_with("gitea.")
+                || host.starts_with("forgejo.") =>
+            {
+                Forge::Gitea
+            }
+            None => Forge::GitHub,
+        }
+    }
+
+    /// API base URL of a host
+    pub fn api(&self, host: &str, forge: Forge) -> String {
+        match (self.hosts.get(host).and_then(|h| h.api.clone()), forge) {
+            (Some(api), _) => api.trim_end_matches('/').to_string(),
+            (None, Forge::GitHub) if host == GITHUB => "https://api.github.com".to_string(),
+            (None, Forge::GitHub) => format!("https://{}/api/v3", host),
+            (None, Forge::GitLab) => format!("https://{}/api/v4", host),
+            (None, Forge::Gitea) => format!("https://{}/api/v1", host),
+        }
+    }
+
+    pub fn token(&self, host: &str) -> Option<String> {
+        let vars: &[&str] = match host {
+            GITHUB => &["GITHUB_TOKEN", "GH_TOKEN"],
+            GITLAB => &["GITLAB_TOKEN"],
+            _ => &[],
+        };
+        vars.iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|token| !token.is_empty())
+            .or_else(|| self.hosts.get(host).and_then(|h| h.token.clone()))
+            .or_else(|| match host {
+                GITHUB => self.github_token.clone(),
+                _ => None,
+            })
+    }
+}
+
+const GITHUB: &str = "github.com";
+const GITLAB: &str = "gitlab.com";
+const CODEBERG: &str = "codeberg.org";
+
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Kind of server a package is released on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    /// Gitea and Forgejo, like Codeberg
+    Gitea,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Host {
+    /// Defaults to `gitlab` for gitlab.com and `gitlab.*` hosts, `gitea` for codeberg.org,
+    /// `gitea.*` and `forgejo.*` hosts, `github` otherwise
+    pub kind: Option<Forge>,
+    /// API base URL, defaults to `https://<host>/api/v3` on GitHub, `https://<host>/api/v4` on
+    /// GitLab and `https://<host>/api/v1` on Gitea
+    pub api: Option<String>,
+    pub token: Option<String>,
+}
+
+/// Kind of server a package source is released on, `owner/repo` is on github.com,
+/// `host/owner/repo` on another server. GitLab projects may be nested in groups like
+/// `gitlab.com/group/subgroup/project`. URLs with a scheme are direct downloads, `None`.
+pub fn forge(url: &str, settings: &Settings) -> anyhow::Result<Option<Forge>> {
+    if url.contains("://") {
+        return Ok(None);
+    }
+    let invalid = || {
+        anyhow::anyhow!(
+            "`{}` is neither a download URL nor a repository like `owner/repo` or `host/owner/repo`",
+            url
+        )
+    };
+    let parts: Vec<&str> = url.split('/').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(invalid());
+    }
+    let host = |part: &str| part.contains('.') || part.contains(':');
+    match parts.as_slice() {
+        [owner, _] if !host(owner) => Ok(Some(Forge::GitHub)),
+        [server, rest @ ..] if rest.len() >= 2 && host(server) => {
+            let forge = settings.forge(server);
+            if forge != Forge::GitLab && rest.len() != 2 {
+                bail!(
+                    "Only GitLab projects may be nested in groups, `{}` is on {:?}",
+                    url,
+                    forge
+                );
+            }
+            Ok(Some(forge))
+        }
+        _ => Err(invalid()),
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Bspm {
+    #[serde(default)]
+    settings: Settings,
     packages: Vec<Package>,
 }
 
@@ -34,8 +222,21 @@ impl Bspm {
             .read_to_string(&mut buffer)
             .await
             .context(format!("Failed to read config file: {}", path.display()))?;
-        Ok(serde_yaml::from_str(&buffer)
-            .context(format!("Invalid BSPM config file: {}", path.display()))?)
+        let mut config: serde_yaml::Value = serde_yaml::from_str(&buffer)
+            .context(format!("Invalid BSPM config file: {}", path.display()))?;
+        let mut migrated = source::migrate(&mut config);
+        let mut bspm: Bspm = serde_yaml::from_value(config)
+            .context(format!("Invalid BSPM config file: {}", path.display()))?;
+        for pkg in bspm.packages.iter_mut() {
+            migrated |= pkg
+                .migrate()
+                .await
+                .context(format!("Failed to migrate backup of {}", pkg.name))?;
+        }
+        if migrated {
+            bspm.write_config().await?;
+        }
+        Ok(bspm)
     }
 
     pub async fn create_config(&self) -> anyhow::Result<()> {
@@ -69,14 +270,79 @@ impl Bspm {
         &mut self,
         name: String,
         url: String,
-        force: bool,
-        compression: Option<installer::Compression>,
-        archive: Option<installer::Archived>,
+        options: InstallOptions,
     ) -> anyhow::Result<()> {
+        let InstallOptions {
+            force,
+            compression,
+            archive,
+            auto,
+            signature,
+            openpgp,
+            tag,
+            constraint,
+            channel,
+            versions,
+            versions_url,
+            versions_json,
+            versions_regex,
+            tree,
+            tree_root,
+        } = options;
+        let mut source = PackageSource::parse(&url, &self.settings)?;
+        let versions = match (versions, versions_url, versions_json, versions_regex) {
+            (None, None, None, None) => None,
+            (Some(repo), None, None, None) => match PackageSource::parse(&repo, &self.settings)? {
+                PackageSource::Url(_) | PackageSource::Template { .. } => {
+                    bail!("Versions can only be taken from a GitHub, GitLab or Gitea repository")
+                }
+                repo => Some(repo),
+            },
+            (None, Some(url), Some(pointer), None) => Some(PackageSource::Json { url, pointer }),
+            (None, Some(url), None, Some(pattern)) => Some(PackageSource::Regex { url, pattern }),
+            _ => bail!(
+                "Use `--versions` with a repository, or `--versions-url` with either `--versions-json` or `--versions-regex`"
+            ),
+        };
+        if let Some(versions) = versions {
+            versions.backend(&self.settings)?;
+            source = match source {
+                PackageSource::Url(url)
+                | PackageSource::Template {
+                    url,
+                    versions: None,
+                } => PackageSource::Template {
+                    url,
+                    versions: Some(Box::new(versions)),
+                },
+                _ => bail!("Only download URLs take their versions from elsewhere"),
+            };
+        }
+        if let PackageSource::Template { url, versions } = &source {
+            template::check(url)?;
+            if template::versioned(url) && versions.is_none() && tag.is_none() {
+                bail!("URL templates with a version need `--tag` or `--versions` to fill it in")
+            }
+        }
+        if (tag.is_some() || constraint.is_some() || channel != Channel::Stable)
+            && !source.versioned()
+        {
+            bail!(
+                "Only packages with versioned releases can follow a tag, version constraint or channel"
+            );
+        }
+        version::constraint(constraint.as_deref())?;
         let ctx = context("🔨", &name).await;
         ctx.notify("Building package").await;
         let mut path = bin_path().await;
         path.push(&name);
+        let tree = match tree {
+            true => Some(Tree {
+                dir: data_path().await.join("opt").join(&name),
+                root: tree_root.map(|root| pattern::wildcard(&root, None)),
+            }),
+            false => None,
+        };
         let mut pkg = Package {
             name: name.clone(),
             installer: Installer {
@@ -85,15 +351,41 @@ impl Bspm {
                 compression,
                 archive,
                 backup: None,
+                entry: None,
+                binaries: Vec::new(),
+                tree,
+                checksum: None,
+                validators: Default::default(),
+                credentials: None,
+                replaces: Vec::new(),
+                staged: Vec::new(),
+                unpacked: None,
             },
             release: None,
             last_update: None,
-            github: None,
+            source,
+            asset: None,
+            asset_override: None,
+            auto,
+            signature,
+            openpgp,
+            history: Vec::new(),
+            keep_versions: None,
+            pin: tag,
+            constraint,
+            channel,
+            assets_updated: None,
         };
         if self.packages.contains(&pkg) {
             let ctx = context("❌", &name).await;
             ctx.notify(&format!("Package is already installed: `{}`", &pkg))
                 .await;
+            if !force && !ui::interactive() {
+                bail!(
+                    "Package `{}` is already installed, use `--force` to reinstall",
+                    &name
+                );
+            }
             if !force && ctx.ask("Enter `y` to force installation").await? != "y" {
                 return Ok(());
             }
@@ -101,9 +393,18 @@ impl Bspm {
                 .await
                 .notify("Installing anyways")
                 .await;
+            if let Some(old) = self.packages.iter().find(|x| *x == &pkg) {
+                pkg.installer.replaces = old
+                    .installer
+                    .paths()
+                    .into_iter()
+                    .map(Path::to_path_buf)
+                    .collect();
+            }
             self.packages.retain(|x| x != &pkg);
         }
-        pkg.install().await?;
+        pkg.install(&self.settings).await?;
+        pkg.snapshot(&self.settings).await;
 
         self.packages.push(pkg);
         self.packages.dedup();
@@ -121,6 +422,7 @@ impl Bspm {
                 continue;
             }
             pkg.installer.uninstall(&ctx).await?;
+            history::remove(name).await?;
             self.packages.remove(i);
             ctx.notify("Package is deleted and removed from disk").await;
             return self
@@ -132,14 +434,32 @@ impl Bspm {
         Ok(())
     }
 
-    pub async fn revert(&mut self, name: &str) -> anyhow::Result<()> {
+    pub async fn revert(&mut self, name: &str, to: Option<&str>) -> anyhow::Result<()> {
         let ctx = context("⏪", name).await;
         ctx.notify("Reverting package").await;
         for pkg in self.packages.iter_mut() {
             if pkg.name != name {
                 continue;
             }
-            pkg.installer.revert(&ctx).await?;
+            pkg.revert(to).await?;
+            return self
+                .write_config()
+                .await
+                .context("failed to save config file");
+        }
+        ctx.notify("This package is not installed").await;
+        Ok(())
+    }
+
+    pub async fn pin(&mut self, name: &str, tag: Option<&str>) -> anyhow::Result<()> {
+        let ctx = context("📌", name).await;
+        for pkg in self.packages.iter_mut() {
+            if pkg.name != name {
+                continue;
+            }
+            let pin = pkg.hold(tag)?;
+            ctx.notify(&format!("Package is pinned to release {}", pin))
+                .await;
             return self
                 .write_config()
                 .await
@@ -149,7 +469,25 @@ impl Bspm {
         Ok(())
     }
 
-    pub async fn update(&mut self, packages: Vec<String>) -> anyhow::Result<()> {
+    pub async fn unpin(&mut self, name: &str) -> anyhow::Result<()> {
+        let ctx = context("📌", name).await;
+        for pkg in self.packages.iter_mut() {
+            if pkg.name != name {
+                continue;
+            }
+            pkg.pin = None;
+            ctx.notify("Package is unpinned and will be updated again")
+                .await;
+            return self
+                .write_config()
+                .await
+                .context("failed to save config file");
+        }
+        ctx.notify("This package is not installed").await;
+        Ok(())
+    }
+
+    pub async fn update(&mut self, packages: Vec<String>, downgrade: bool) -> anyhow::Result<()> {
         if self.packages.is_empty() {
             context("🏜 ", "blindspot")
                 .await
@@ -163,9 +501,10 @@ impl Bspm {
                 continue;
             }
             let pkg = pkg.clone();
+            let settings = self.settings.clone();
             handles.push(std::thread::spawn(move || {
                 smol::spawn(async move {
-                    let result = pkg.update().await;
+                    let result = pkg.update(&settings, downgrade).await;
                     let ctx = context("❌", &pkg.name).await;
                     if result.is_err() {
                         ctx.notify(&format!("Update failed: {:?}", &result).replace("\n", "."))
@@ -175,10 +514,14 @@ impl Bspm {
                 })
             }));
         }
+        let mut failed = 0;
         for handle in handles {
             let updated = match handle.join().expect("Thread join failure").await {
                 Ok(pkg) => pkg,
-                Err(_) => continue,
+                Err(_) => {
+                    failed += 1;
+                    continue;
+                }
             };
             for (i, pkg) in self.packages.iter().enumerate() {
                 if *pkg == updated {
@@ -190,17 +533,34 @@ impl Bspm {
         }
         self.write_config()
             .await
-            .context("failed to save config file")
+            .context("failed to save config file")?;
+        if failed > 0 {
+            bail!("{} package(s) failed to update", failed);
+        }
+        Ok(())
+    }
+
+    pub fn history(&self, name: &str) -> anyhow::Result<()> {
+        match self.packages.iter().find(|pkg| pkg.name == name) {
+            Some(pkg) => {
+                pkg.print_history();
+                Ok(())
+            }
+            None => bail!("Package `{}` is not installed", name),
+        }
     }
 
     pub fn list(&self) {
         for pkg in &self.packages {
             println!(
-                "{}{}{} {}",
+                "{}{}{} {} {}",
                 termion::style::Bold,
                 pkg.name,
                 termion::style::Reset,
-                pkg.github.as_ref().unwrap_or(&pkg.installer.url),
+                pkg.release
+                    .as_ref()
+                    .map_or("(not installed)".to_string(), Release::to_string),
+                pkg.source,
             )
         }
     }
@@ -261,3 +621,111 @@ pub async fn data_path() -> PathBuf {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        let host = |kind, api: Option<&str>, token: Option<&str>| Host {
+            kind,
+            api: api.map(str::to_string),
+            token: token.map(str::to_string),
+        };
+        Settings {
+            hosts: BTreeMap::from([
+                (
+                    "ghe.corp".to_string(),
+                    host(None, Some("https://api.ghe.corp/"), Some("ghe-token")),
+                ),
+                (
+                    "git.corp:8443".to_string(),
+                    host(Some(Forge::GitLab), None, Some("corp-token")),
+                ),
+                (
+                    "code.corp".to_string(),
+                    host(Some(Forge::Gitea), None, None),
+                ),
+            ]),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn forges() {
+        let settings = settings();
+        for (url, expected) in [
+            ("BurntSushi/ripgrep", Some(Forge::GitHub)),
+            ("github.com/BurntSushi/ripgrep", Some(Forge::GitHub)),
+            ("ghe.corp/org/tool", Some(Forge::GitHub)),
+            ("localhost:3000/org/tool", Some(Forge::GitHub)),
+            ("gitlab.com/group/project", Some(Forge::GitLab)),
+            ("gitlab.com/group/sub/project", Some(Forge::GitLab)),
+            ("gitlab.example.com/a/b/c/d", Some(Forge::GitLab)),
+            ("git.corp:8443/group/sub/project", Some(Forge::GitLab)),
+            ("codeberg.org/owner/repo", Some(Forge::Gitea)),
+            ("forgejo.example.com/owner/repo", Some(Forge::Gitea)),
+            ("code.corp/owner/repo", Some(Forge::Gitea)),
+            ("https://example.com/tool", None),
+            ("https://github.com/a/b/c", None),
+            ("http://localhost:8080/tool.tar.gz", None),
+        ] {
+            assert_eq!(forge(url, &settings).unwrap(), expected, "{}", url);
+        }
+        for url in [
+            "github.com/a/b/c",
+            "codeberg.org/a/b/c",
+            "example.com/tool",
+            "localhost:3000/tool",
+            "tool",
+            "owner//repo",
+            "owner/repo/",
+            "a/b/c",
+        ] {
+            assert!(forge(url, &settings).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn hosts() {
+        let settings = settings();
+        for (host, forge, api) in [
+            ("github.com", Forge::GitHub, "https://api.github.com"),
+            ("ghe.corp", Forge::GitHub, "https://api.ghe.corp"),
+            (
+                "ghe.example.com",
+                Forge::GitHub,
+                "https://ghe.example.com/api/v3",
+            ),
+            ("gitlab.com", Forge::GitLab, "https://gitlab.com/api/v4"),
+            (
+                "gitlab.example.com",
+                Forge::GitLab,
+                "https://gitlab.example.com/api/v4",
+            ),
+            (
+                "git.corp:8443",
+                Forge::GitLab,
+                "https://git.corp:8443/api/v4",
+            ),
+            ("codeberg.org", Forge::Gitea, "https://codeberg.org/api/v1"),
+            (
+                "gitea.example.com",
+                Forge::Gitea,
+                "https://gitea.example.com/api/v1",
+            ),
+            ("code.corp", Forge::Gitea, "https://code.corp/api/v1"),
+        ] {
+            assert_eq!(settings.forge(host), forge, "{}", host);
+            assert_eq!(settings.api(host, forge), api, "{}", host);
+        }
+        for (host, token) in [
+            ("ghe.corp", Some("ghe-token")),
+            ("git.corp:8443", Some("corp-token")),
+            ("code.corp", None),
+            ("gitlab.example.com", None),
+        ] {
+            assert_eq!(settings.token(host).as_deref(), token, "{}", host);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::{anyhow, bail, Context};
+use async_std::fs::{create_dir_all, read_to_string, write};
+use isahc::config::RedirectPolicy;
+use isahc::prelude::*;
+use pgp::types::PublicKeyTrait;
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+use std::path::{Path, PathBuf};
+
+use super::{data_path, ui};
+
+const KEYSERVER: &str = "https://keys.openpgp.org/vks/v1";
+
+/// OpenPGP verification using trust on first use:
+/// The signing key gets pinned by its fingerprint on installation and must not change afterwards.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OpenPgp {
+    /// URL or path of the signing key, the keyserver is asked if this is not set
+    pub key: Option<String>,
+    /// Fingerprint of the pinned signing key
+    pub fingerprint: Option<String>,
+}
+
+impl OpenPgp {
+    pub async fn verify(
+        &mut self,
+        ctx: &ui::Context,
+        data: &[u8],
+        signature: &str,
+    ) -> anyhow::Result<()> {
+        let keyring = data_path().await.join("keyring");
+        self.verify_in(ctx, &keyring, data, signature).await
+    }
+
+    async fn verify_in(
+        &mut self,
+        ctx: &ui::Context,
+        keyring: &Path,
+        data: &[u8],
+        signature: &str,
+    ) -> anyhow::Result<()> {
+        let (signature, _) = StandaloneSignature::from_string(signature)
+            .map_err(|e| anyhow!("Invalid OpenPGP signature: {}", e))?;
+        let issuers: Vec<String> = signature
+            .signature
+            .issuer_fingerprint()
+            .iter()
+            .map(|fp| hex::encode_upper(fp.as_bytes()))
+            .chain(
+                signature
+                    .signature
+                    .issuer()
+                    .iter()
+                    .map(|id| format!("{:X}", id)),
+            )
+            .collect();
+        let key = match &self.fingerprint {
+            Some(fingerprint) => load_key(keyring, fingerprint).await?,
+            None => self.fetch_key(ctx, keyring, &issuers).await?,
+        };
+        let fingerprint = hex::encode_upper(key.fingerprint().as_bytes());
+        // The primary key or a subkey has to be one of the issuers
+        let result = if is_issuer(&key, &issuers) {
+            Some(signature.verify(&key, data))
+        } else {
+            key.public_subkeys
+                .iter()
+                .find(|k| is_issuer(*k, &issuers))
+                .map(|k| signature.verify(k, data))
+        };
+        match result {
+            Some(result) => result.map_err(|e| anyhow!("Bad OpenPGP signature: {}", e))?,
+            None => bail!(
+                "OpenPGP SIGNING KEY CHANGED!\nPinned key: {}\nSignature issuer: {}\nRefusing to continue, reinstall the package if this change is legit",
+                &fingerprint,
+                issuers.join(", ")
+            ),
+        }
+        ctx.notify(&format!(
+            "OpenPGP signature verified (key {})",
+            &fingerprint
+        ))
+        .await;
+        if self.fingerprint.is_none() {
+            ctx.notify(&format!("Pinning OpenPGP key {}", &fingerprint))
+                .await;
+            self.fingerprint = Some(fingerprint);
+        }
+        Ok(())
+    }
+
+    /// Get the key on first use and put it into the keyring if it made the signature
+    async fn fetch_key(
+        &self,
+        ctx: &ui::Context,
+        keyring: &Path,
+        issuers: &[String],
+    ) -> anyhow::Result<SignedPublicKey> {
+        let armored = match &self.key {
+            Some(key) if !key.starts_with("http://") && !key.starts_with("https://") => {
+                read_to_string(key)
+                    .await
+                    .context(format!("Failed to read OpenPGP key {}", key))?
+            }
+            Some(url) => fetch(url).await?,
+            None => {
+                let issuer = issuers
+                    .first()
+                    .ok_or_else(|| anyhow!("OpenPGP signature has no issuer"))?;
+                let url = match issuer.len() {
+                    16 => format!("{}/by-keyid/{}", KEYSERVER, issuer),
+                    _ => format!("{}/by-fingerprint/{}", KEYSERVER, issuer),
+                };
+                ctx.notify(&format!("Fetching OpenPGP key {}", &url)).await;
+                fetch(&url).await?
+            }
+        };
+        let (key, _) = SignedPublicKey::from_string(&armored)
+            .map_err(|e| anyhow!("Invalid OpenPGP key: {}", e))?;
+        key.verify()
+            .map_err(|e| anyhow!("Invalid OpenPGP key: {}", e))?;
+        let fingerprint = hex::encode_upper(key.fingerprint().as_bytes());
+        if !is_issuer(&key, issuers) && !key.public_subkeys.iter().any(|k| is_issuer(k, issuers)) {
+            bail!(
+                "OpenPGP key {} did not make the signature\nSignature issuer: {}\nCheck the key the package is installed with",
+                &fingerprint,
+                issuers.join(", ")
+            )
+        }
+        ctx.notify(&format!(
+            "Adding OpenPGP key {} to the keyring",
+            &fingerprint
+        ))
+        .await;
+        create_dir_all(keyring).await?;
+        let path = keyring_path(keyring, &fingerprint);
+        write(&path, armored)
+            .await
+            .context(format!("Failed to write {}", path.display()))?;
+        Ok(key)
+    }
+}
+
+fn is_issuer(key: &impl PublicKeyTrait, issuers: &[String]) -> bool {
+    let fingerprint = hex::encode_upper(key.fingerprint().as_bytes());
+    let key_id = format!("{:X}", key.key_id());
+    issuers.iter().any(|i| *i == fingerprint || *i == key_id)
+}
+
+async fn load_key(keyring: &Path, fingerprint: &str) -> anyhow::Result<SignedPublicKey> {
+    let path = keyring_path(keyring, fingerprint);
+    let armored = read_to_string(&path).await.context(format!(
+        "Pinned OpenPGP key is missing from the keyring: {}",
+        path.display()
+    ))?;
+    let (key, _) = SignedPublicKey::from_string(&armored)
+        .map_err(|e| anyhow!("Invalid OpenPGP key {}: {}", path.display(), e))?;
+    if hex::encode_upper(key.fingerprint().as_bytes()) != fingerprint {
+        bail!(
+            "Keyring entry {} does not match its fingerprint",
+            path.display()
+        )
+    }
+    Ok(key)
+}
+
+fn keyring_path(keyring: &Path, fingerprint: &str) -> PathBuf {
+    keyring.join(format!("{}.asc", fingerprint))
+}
+
+async fn fetch(url: &str) -> anyhow::Result<String> {
+    let mut response = isahc::Request::get(url)
+        .redirect_policy(RedirectPolicy::Limit(50))
+        .body(())
+        .context("Failed to build request body")?
+        .send_async()
+        .await
+        .context(url.to_string())?;
+    if response.status() != 200 {
+        bail!("Status: {}\nURL: {}", response.status(), url)
+    }
+    Ok(response.text().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatLY0BYJKwYBBAHaRw8BAQdANlziWzDOBq6f25uIGlSc+45gt+kSIkvtKSHk
+iCfS7s+0GWFsaWNlIDxhbGljZUBleGFtcGxlLmNvbT6IkAQTFggAOBYhBPpI6xVI
+Ae+cj5kAiVIQ5aFaW+TvBQJq0tjQAhsDBQsJCAcCBhUKCQgLAgQWAgMBAh4BAheA
+AAoJEFIQ5aFaW+Tv94kBAKK36BI5pQwJhQJGX+S4xiaYw7a23cP1gdhdavMEK99b
+AP9AoLE+XICpECPP0FZCSCEtDNWCmtTPFXXh8DJYpfeqAg==
+=sQWJ
+-----END PGP PUBLIC KEY BLOCK-----";
+
+    const ALICE_FINGERPRINT: &str = "FA48EB154801EF9C8F9900895210E5A15A5BE4EF";
+
+    // Detached signature of `tool`
+    const ALICE_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----
+
+iIgEABYIADAWIQT6SOsVSAHvnI+ZAIlSEOWhWlvk7wUCatLY0BIcYWxpY2VAZXhh
+bXBsZS5jb20ACgkQUhDloVpb5O8HTQEAxJMAPo6xysXNRb/c3PO39Yc0LO2okNgt
+GWYFam/Ys5UA+gLnBBIHwk0zo6Z/0krWv65Dcv3XJa7LFZyquTwZqhAH
+=VtgB
+-----END PGP SIGNATURE-----";
+
+    const BOB: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatLY0BYJKwYBBAHaRw8BAQdAP8Z4qLT68aYMar+1VBo7KnBTwoIjtHEtrT5n
+yRvftza0FWJvYiA8Ym9iQGV4YW1wbGUuY29tPoiQBBMWCAA4FiEEMURF0yt+jN8v
+FIljcJcQV9jjkjIFAmrS2NACGwMFCwkIBwIGFQoJCAsCBBYCAwECHgECF4AACgkQ
+cJcQV9jjkjLsnQEA6KZHUD+RFfnA1AvcgVisJGrW6sAl4EqSQYmdZHY1m2QBAI33
+C6pURgNcWB6xG+IHYMjJVZqEt/JHuRJqrQrXDEcK
+=z/ze
+-----END PGP PUBLIC KEY BLOCK-----";
+
+    const BOB_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----
+
+iIYEABYIAC4WIQQxREXTK36M3y8UiWNwlxBX2OOSMgUCatLY0BAcYm9iQGV4YW1w
+bGUuY29tAAoJEHCXEFfY45Iya7cBAMk6ZbfduJ9BHjqYuVrvhdGNTt+NS2daezKp
+3QKptVy5AQDYF5mbnc9hJ78QCqLjNa8bWLvJH5ey4e0XrgYU1HiYDQ==
+=DPCR
+-----END PGP SIGNATURE-----";
+
+    #[test]
+    fn pinning() {
+        let dir = std::env::temp_dir().join("blindspot-test-openpgp");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let keyring = dir.join("keyring");
+        for (name, key) in [("alice.asc", ALICE), ("bob.asc", BOB)] {
+            std::fs::write(dir.join(name), key).unwrap();
+        }
+        let openpgp = |key: &str| OpenPgp {
+            key: Some(dir.join(key).display().to_string()),
+            fingerprint: None,
+        };
+        smol::block_on(async {
+            let ctx = ui::context("🧪", "test").await;
+            let error = |result: anyhow::Result<()>| format!("{:#}", result.unwrap_err());
+            // A key that did not make the signature never gets into the keyring or pinned
+            let mut wrong = openpgp("bob.asc");
+            let err = error(
+                wrong
+                    .verify_in(&ctx, &keyring, b"tool", ALICE_SIGNATURE)
+                    .await,
+            );
+            assert!(err.contains("did not make the signature"), "{}", err);
+            assert!(!err.contains("CHANGED"), "{}", err);
+            assert_eq!(wrong.fingerprint, None);
+            assert!(!keyring.exists());
+            // The first verification pins the key
+            let mut pgp = openpgp("alice.asc");
+            pgp.verify_in(&ctx, &keyring, b"tool", ALICE_SIGNATURE)
+                .await
+                .unwrap();
+            assert_eq!(pgp.fingerprint.as_deref(), Some(ALICE_FINGERPRINT));
+            let entry = keyring_path(&keyring, ALICE_FINGERPRINT);
+            assert!(entry.exists());
+            // Later verifications only trust the pinned key from the keyring
+            pgp.key = Some(dir.join("bob.asc").display().to_string());
+            pgp.verify_in(&ctx, &keyring, b"tool", ALICE_SIGNATURE)
+                .await
+                .unwrap();
+            let err = error(pgp.verify_in(&ctx, &keyring, b"tool", BOB_SIGNATURE).await);
+            assert!(err.contains("SIGNING KEY CHANGED"), "{}", err);
+            let err = error(
+                pgp.verify_in(&ctx, &keyring, b"tampered", ALICE_SIGNATURE)
+                    .await,
+            );
+            assert!(err.contains("Bad OpenPGP signature"), "{}", err);
+            std::fs::write(&entry, BOB).unwrap();
+            let err = error(
+                pgp.verify_in(&ctx, &keyring, b"tool", ALICE_SIGNATURE)
+                    .await,
+            );
+            assert!(err.contains("does not match its fingerprint"), "{}", err);
+            std::fs::remove_file(&entry).unwrap();
+            let err = error(
+                pgp.verify_in(&ctx, &keyring, b"tool", ALICE_SIGNATURE)
+                    .await,
+            );
+            assert!(err.contains("missing from the keyring"), "{}", err);
+            assert_eq!(pgp.fingerprint.as_deref(), Some(ALICE_FINGERPRINT));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
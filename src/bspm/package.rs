@@ -1,10 +1,17 @@
-use anyhow::{anyhow, bail, Context};
+use anyhow::{bail, Context};
 use chrono::prelude::*;
 use isahc::prelude::*;
-use serde_json::Value;
-use std::str::FromStr;
 
-use super::{installer::Installer, ui::context};
+use super::{
+    history::{self, Snapshot},
+    installer::Installer,
+    openpgp::OpenPgp,
+    pattern, platform,
+    source::{Artifact, Candidate, PackageSource, Source},
+    ui::context,
+    verify::{parse_checksums, Checksum, Signature},
+    version, Settings,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Package {
@@ -12,120 +19,433 @@ pub struct Package {
     pub installer: Installer,
     pub release: Option<Release>,
     pub last_update: Option<DateTime<Utc>>,
-    pub github: Option<String>,
+    pub source: PackageSource,
+    pub asset: Option<String>,
+    /// Asset pattern set by the user, wins over the remembered asset and is never replaced
+    pub asset_override: Option<String>,
+    #[serde(default)]
+    pub auto: bool,
+    pub signature: Option<Signature>,
+    pub openpgp: Option<OpenPgp>,
+    #[serde(default)]
+    pub history: Vec<Snapshot>,
+    pub keep_versions: Option<usize>,
+    /// Release the package is held at, updates skip it
+    pub pin: Option<String>,
+    /// Semver requirement releases have to satisfy, like `^1.4` or `~0.9`
+    pub constraint: Option<String>,
+    #[serde(default)]
+    pub channel: Channel,
+    pub assets_updated: Option<DateTime<Utc>>,
 }
 
 impl Package {
-    pub async fn install(&mut self) -> anyhow::Result<()> {
-        let ctx = context("📦", &self.name).await;
-        if self.installer.url.split('/').count() != 2 {
-            self.release = Some(Release::Dated(Utc::now()));
-            return self.installer.install(&ctx).await;
-        }
-        let github_release: Value = self
-            .github_get_latest_release(&self.installer.url)
+    pub async fn install(&mut self, settings: &Settings) -> anyhow::Result<()> {
+        let source = self.backend(settings).await?;
+        let candidate = source
+            .latest(self)
             .await
-            .context("Failed to resolve GitHub repository")?;
-        self.github = Some(self.installer.url.clone());
-        self.installer.url = self.github_dl_url(&github_release).await?;
-        self.release = Some(Release::Version(self.github_tag_name(&github_release)?));
-        self.installer.install(&ctx).await
+            .context("Failed to resolve release")?;
+        self.install_candidate(&*source, &candidate, false).await?;
+        Ok(())
     }
 
-    pub async fn update(&self) -> anyhow::Result<Package> {
+    pub async fn update(&self, settings: &Settings, downgrade: bool) -> anyhow::Result<Package> {
         let mut pkg = self.clone();
         let ctx = context("⛽", &self.name).await;
         ctx.notify("Updating package").await;
         ctx.notify(&format!("Last update: {:?}", self.last_update))
             .await;
-        if pkg.github.is_none() {
-            pkg.install().await?;
-            pkg.last_update = Some(Utc::now());
-            return Ok(pkg);
+        if let (Some(pin), Some(release)) = (&pkg.pin, &pkg.release) {
+            if *pin == release.to_string() {
+                ctx.notify(&format!("Package is held at release {}", pin))
+                    .await;
+                return Ok(pkg);
+            }
         }
-        let installed_release = match &pkg.release {
-            Some(Release::Version(current)) => current.clone(),
-            _ => bail!("Corrupted package (please reinstall)"),
-        };
-        let github_release: Value = pkg
-            .github_get_latest_release(pkg.github.as_ref().unwrap())
+        let source = pkg.backend(settings).await?;
+        let candidate = source
+            .latest(&pkg)
             .await
-            .context("Failed to resolve GitHub repository")?;
-        let latest_release = pkg.github_tag_name(&github_release)?;
-        ctx.notify(&format!("Installed release: {}", installed_release))
-            .await;
-        ctx.notify(&format!("Latest release: {}", &latest_release))
-            .await;
-        if latest_release == installed_release {
+            .context("Failed to resolve release")?;
+        if let (Some(Release::Version(installed_release)), Release::Version(latest_release)) =
+            (&pkg.release, &candidate.release)
+        {
+            ctx.notify(&format!("Installed release: {}", installed_release))
+                .await;
+            ctx.notify(&format!("Latest release: {}", latest_release))
+                .await;
+            let installed_version = version::parse(installed_release);
+            let latest_version = version::parse(latest_release);
+            if pkg.republished(&candidate) {
+                ctx.notify("Release assets were published again").await;
+            } else if latest_release == installed_release
+                || (latest_version.is_some() && latest_version == installed_version)
+            {
+                ctx.notify("Looks like the latest release is already installed")
+                    .await;
+                return Ok(pkg);
+            }
+            if let (Some(latest), Some(installed), None, false, false) = (
+                &latest_version,
+                &installed_version,
+                &pkg.pin,
+                downgrade,
+                pkg.channel == Channel::Nightly,
+            ) {
+                if latest < installed {
+                    ctx.notify(&format!(
+                        "Release {} is older than the installed release, use `--allow-downgrade` to install it anyways",
+                        latest_release
+                    ))
+                    .await;
+                    return Ok(pkg);
+                }
+            }
+            ctx.notify(&format!("Other release available: {}", latest_release))
+                .await;
+        }
+        // Direct downloads are only installed again if the server has a different file
+        let conditional = matches!(candidate.release, Release::Dated(_));
+        if !pkg
+            .install_candidate(&*source, &candidate, conditional)
+            .await?
+        {
             ctx.notify("Looks like the latest release is already installed")
                 .await;
             return Ok(pkg);
         }
-        ctx.notify(&format!("Other release available: {}", latest_release))
-            .await;
-        pkg.installer.url = pkg.github_dl_url(&github_release).await?;
-        pkg.install().await?;
-        pkg.release = Some(Release::Version(latest_release));
         pkg.last_update = Some(Utc::now());
+        pkg.snapshot(settings).await;
         Ok(pkg)
     }
 
-    fn github_tag_name(&self, release: &Value) -> anyhow::Result<String> {
-        let result;
-        match &release["tag_name"] {
-            Value::String(v) => result = v.to_string(),
-            _ => bail!("No browser download url in asset"),
+    /// Whether a nightly release got new assets under the installed tag
+    fn republished(&self, candidate: &Candidate) -> bool {
+        self.channel == Channel::Nightly && candidate.updated() > self.assets_updated
+    }
+
+    /// Keep a copy of the installed release in the history store. The release is live already,
+    /// so a failure only costs the ability to revert to it
+    pub async fn snapshot(&mut self, settings: &Settings) {
+        if let Err(err) = self.save_snapshot(settings).await {
+            context("📦", &self.name)
+                .await
+                .notify(&format!(
+                    "Warning: Failed to keep the release in the history: {:#}",
+                    err
+                ))
+                .await;
         }
-        Ok(result)
     }
 
-    async fn github_dl_url(&self, release: &Value) -> anyhow::Result<String> {
-        let ctx = context("🪐", &self.name).await;
-        let assets = match &release["assets"] {
-            Value::Array(v) => v,
-            _ => bail!("No assets in release"),
+    async fn save_snapshot(&mut self, settings: &Settings) -> anyhow::Result<()> {
+        let release = match &self.release {
+            Some(release) => release.clone(),
+            None => return Ok(()),
+        };
+        let snapshot = match self.installer.tree_path(&release) {
+            Some(dir) => history::keep(&release, dir, &self.installer.binaries),
+            None => {
+                history::save(
+                    &self.name,
+                    &release,
+                    &self.installer.paths(),
+                    &self.installer.binaries,
+                )
+                .await?
+            }
+        };
+        self.history.retain(|s| s.release != release);
+        self.history.push(snapshot);
+        history::prune(
+            &mut self.history,
+            self.keep_versions.unwrap_or(settings.keep_versions),
+            self.release.as_ref(),
+        )
+        .await
+    }
+
+    /// Switch to a release from the history, defaults to the one installed before the current one
+    pub async fn revert(&mut self, to: Option<&str>) -> anyhow::Result<()> {
+        let ctx = context("⏪", &self.name).await;
+        let current = self
+            .history
+            .iter()
+            .position(|s| Some(&s.release) == self.release.as_ref())
+            .unwrap_or(self.history.len());
+        let snapshot = match to {
+            Some(to) => self.history.iter().find(|s| {
+                s.release.to_string().trim_start_matches('v') == to.trim_start_matches('v')
+            }),
+            None => current.checked_sub(1).and_then(|i| self.history.get(i)),
+        };
+        let snapshot = match (snapshot, to) {
+            (Some(snapshot), _) => snapshot.clone(),
+            (None, Some(to)) => bail!(
+                "Release {} is not in the history, available: {}",
+                to,
+                self.history
+                    .iter()
+                    .map(|s| s.release.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            (None, None) => {
+                ctx.notify("No matching version found in the history, doing nothing")
+                    .await;
+                return Ok(());
+            }
+        };
+        ctx.notify(&format!("Reverting to release {}", snapshot.release))
+            .await;
+        self.installer
+            .restore(&ctx, &snapshot.path, &snapshot.binaries)
+            .await?;
+        self.release = Some(snapshot.release);
+        Ok(())
+    }
+
+    /// Hold the package at a release, defaults to the installed one
+    pub fn hold(&mut self, tag: Option<&str>) -> anyhow::Result<String> {
+        let pin = match (tag, &self.release) {
+            (Some(_), _) if !self.source.versioned() => {
+                bail!("Only packages with versioned releases can be pinned to a tag")
+            }
+            (Some(tag), _) => tag.to_string(),
+            (None, Some(release)) => release.to_string(),
+            (None, None) => bail!("Package has no release to pin"),
+        };
+        self.pin = Some(pin.clone());
+        Ok(pin)
+    }
+
+    pub fn print_history(&self) {
+        for snapshot in &self.history {
+            println!(
+                "{}{}{} installed {}{}",
+                termion::style::Bold,
+                snapshot.release,
+                termion::style::Reset,
+                snapshot.installed.format("%Y-%m-%d %H:%M"),
+                if Some(&snapshot.release) == self.release.as_ref() {
+                    " (current)"
+                } else {
+                    ""
+                },
+            )
+        }
+    }
+
+    /// Move the single backup of older versions into the history store
+    pub async fn migrate(&mut self) -> anyhow::Result<bool> {
+        let backup = match self.installer.backup.take() {
+            Some(backup) if backup.exists() => backup,
+            _ => return Ok(false),
         };
+        let modified: DateTime<Utc> = backup.metadata()?.modified()?.into();
+        self.history
+            .push(history::save(&self.name, &Release::Dated(modified), &[&backup], &[]).await?);
+        if let (Some(release), true) = (&self.release, self.installer.path.exists()) {
+            self.history.push(
+                history::save(
+                    &self.name,
+                    release,
+                    &self.installer.paths(),
+                    &self.installer.binaries,
+                )
+                .await?,
+            );
+        }
+        async_std::fs::remove_file(&backup).await?;
+        Ok(true)
+    }
+
+    async fn backend(&self, settings: &Settings) -> anyhow::Result<Box<dyn Source>> {
+        let source = self.source.backend(settings)?;
+        context("🪐", &self.name)
+            .await
+            .notify(&format!("Treating package as a {}", source.describe()))
+            .await;
+        Ok(source)
+    }
+
+    /// Returns `false` if the conditional download found the installed file unchanged
+    async fn install_candidate(
+        &mut self,
+        source: &dyn Source,
+        candidate: &Candidate,
+        conditional: bool,
+    ) -> anyhow::Result<bool> {
+        let ctx = context("📦", &self.name).await;
+        let artifact = self.pick_artifact(candidate).await?;
+        self.installer.credentials = source.credentials();
+        self.installer.checksum = match &candidate.checksum {
+            Some(checksum) => Some(checksum.clone()),
+            None => self.find_checksum(candidate, &artifact).await?,
+        };
+        self.installer.url = artifact.url;
+        let installed = self
+            .installer
+            .install(
+                &ctx,
+                &candidate.release,
+                self.signature.as_ref(),
+                self.openpgp.as_mut(),
+                conditional,
+            )
+            .await?;
+        if installed {
+            self.release = Some(candidate.release.clone());
+            self.assets_updated = candidate.updated();
+        }
+        Ok(installed)
+    }
+
+    async fn pick_artifact(&mut self, candidate: &Candidate) -> anyhow::Result<Artifact> {
+        let artifacts = &candidate.artifacts;
+        // Nothing to choose from, like a direct download
+        if let [artifact] = artifacts.as_slice() {
+            return Ok(artifact.clone());
+        }
+        let ctx = context("🪐", &self.name).await;
+        let tag_name = candidate.release.to_string();
         ctx.notify(&format!(
             "Release {} ships {} assets...",
-            self.github_tag_name(release)?,
-            assets.len()
+            &tag_name,
+            artifacts.len()
         ))
         .await;
-        if assets.is_empty() {
+        if artifacts.is_empty() {
             bail!("No assets in release")
         }
-        for (i, asset) in assets.iter().enumerate() {
+        if let Some(pattern) = &self.asset_override {
+            let matching: Vec<&Artifact> = artifacts
+                .iter()
+                .filter(|a| pattern::matches(pattern, &a.name))
+                .collect();
+            return match matching.as_slice() {
+                [artifact] => {
+                    ctx.notify(&format!(
+                        "Using asset {} (matches override `{}`)",
+                        artifact.name, pattern
+                    ))
+                    .await;
+                    Ok((*artifact).clone())
+                }
+                _ => bail!(
+                    "{} assets of release {} match the override `{}`",
+                    matching.len(),
+                    &tag_name,
+                    pattern
+                ),
+            };
+        }
+        if let Some(asset) = &self.asset {
+            let matching: Vec<&Artifact> = artifacts
+                .iter()
+                .filter(|a| pattern::matches(asset, &a.name))
+                .collect();
+            if matching.len() == 1 {
+                ctx.notify(&format!(
+                    "Using asset {} (matches `{}`)",
+                    matching[0].name, asset
+                ))
+                .await;
+                return Ok(matching[0].clone());
+            }
             ctx.notify(&format!(
-                "{}-> {}{}\t{:.2}mb\t{}",
+                "{} assets match `{}`, please choose again",
+                matching.len(),
+                asset
+            ))
+            .await;
+        }
+        let mut ranked: Vec<(&Artifact, i32)> = artifacts
+            .iter()
+            .filter_map(|a| platform::score(&a.name).map(|score| (a, score)))
+            .collect();
+        if ranked.is_empty() {
+            ctx.notify("No asset looks like it fits this host, showing all")
+                .await;
+            ranked = artifacts.iter().map(|a| (a, 0)).collect();
+        }
+        ranked.sort_by_key(|(_, score)| -score);
+        let best = match ranked.as_slice() {
+            [_] => Some(0),
+            [(_, first), (_, second), ..] if first > second => Some(0),
+            _ => None,
+        };
+        for (i, (artifact, _)) in ranked.iter().enumerate() {
+            ctx.notify(&format!(
+                "{}-> {}{}\t{}\t{}{}",
                 termion::style::Bold,
                 i,
                 termion::style::Reset,
-                f32::from_str(&asset["size"].to_string())? / 1_000_000.0,
-                asset["name"],
+                artifact.size.map_or("?".to_string(), |size| format!(
+                    "{:.2}mb",
+                    size as f64 / 1_000_000.0
+                )),
+                artifact.name,
+                if best == Some(i) {
+                    " (recommended)"
+                } else {
+                    ""
+                },
             ))
             .await;
         }
-        let pick = ctx.ask_number(0, assets.len(), "Choose one:").await?;
-        match &assets[pick]["browser_download_url"] {
-            Value::String(v) => Ok(v.to_string()),
-            _ => Err(anyhow!("No browser download url in asset")),
-        }
+        let pick = match best {
+            Some(best) if self.auto => {
+                ctx.notify(&format!("Automatically chose {}", ranked[best].0.name))
+                    .await;
+                best
+            }
+            _ => ctx.ask_number(0, ranked.len(), best, "Choose one:").await?,
+        };
+        let artifact = ranked[pick].0.clone();
+        self.asset = Some(pattern::wildcard(&artifact.name, Some(&tag_name)));
+        Ok(artifact)
     }
 
-    async fn github_get_latest_release(&self, repo: &str) -> anyhow::Result<Value> {
-        let ctx = context("🪐", &self.name).await;
-        ctx.notify("Treating package as a GitHub repository").await;
-        let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
-        let mut response = isahc::Request::get(&url)
-            .body(())
-            .context("Failed to build request body")?
-            .send_async()
-            .await
-            .context(url.clone())?;
-        if response.status() != 200 {
-            bail!("Status: {}\nURL: {}", &url, response.status())
+    /// Look for a checksum of the chosen asset in the checksum files shipped with the release
+    async fn find_checksum(
+        &self,
+        candidate: &Candidate,
+        artifact: &Artifact,
+    ) -> anyhow::Result<Option<Checksum>> {
+        if candidate.artifacts.len() < 2 {
+            return Ok(None);
+        }
+        let ctx = context("🔏", &self.name).await;
+        let name = &artifact.name;
+        let names = [
+            format!("{}.sha256", name),
+            format!("{}.sha512", name),
+            format!("{}.sha256sum", name),
+            format!("{}.sha512sum", name),
+            "SHA256SUMS".to_string(),
+            "SHA512SUMS".to_string(),
+            "*checksums*.txt".to_string(),
+            "*sha256sums*".to_string(),
+            "*SHA256SUMS*".to_string(),
+        ];
+        for checksums in names.iter() {
+            for file in candidate
+                .artifacts
+                .iter()
+                .filter(|a| pattern::matches(checksums, &a.name))
+            {
+                let text = self.installer.get(&file.url).await?.text().await?;
+                if let Some(checksum) = parse_checksums(&text, name) {
+                    ctx.notify(&format!("Found checksum in {}", file.name))
+                        .await;
+                    return Ok(Some(checksum));
+                }
+            }
         }
-        Ok(response.json().await?)
+        ctx.notify("Release ships no checksum for this asset").await;
+        Ok(None)
     }
 }
 
@@ -141,8 +461,102 @@ impl std::fmt::Display for Package {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Release {
     Version(String),
     Dated(DateTime<Utc>),
 }
+
+impl std::fmt::Display for Release {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Release::Version(v) => write!(f, "{}", v),
+            Release::Dated(d) => write!(f, "{}", d.format("%Y-%m-%dT%H:%M:%S")),
+        }
+    }
+}
+
+/// Which GitHub releases a package follows
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// Releases not marked as prerelease, highest version first
+    #[default]
+    Stable,
+    /// All releases, highest version first
+    Prerelease,
+    /// The most recently published release, whatever its tag
+    Nightly,
+}
+
+impl Channel {
+    pub fn variants() -> [&'static str; 3] {
+        ["stable", "prerelease", "nightly"]
+    }
+}
+
+impl std::str::FromStr for Channel {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stable" => Ok(Channel::Stable),
+            "prerelease" => Ok(Channel::Prerelease),
+            "nightly" => Ok(Channel::Nightly),
+            _ => Err(format!("Invalid channel: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub fn package(channel: Channel, constraint: Option<&str>) -> Package {
+        let mut pkg: Package = serde_yaml::from_str(
+            r#"
+name: tool
+installer:
+  url: "https://github.com/owner/tool/releases/download/v1.0.0/tool"
+  path: /home/me/.local/bin/tool
+  compression: ~
+  archive: ~
+release:
+  Version: v1.0.0
+last_update: ~
+source:
+  GitHub: owner/tool
+"#,
+        )
+        .unwrap();
+        pkg.channel = channel;
+        pkg.constraint = constraint.map(str::to_string);
+        pkg
+    }
+
+    pub fn candidate(tag: &str, prerelease: bool, day: u32) -> Candidate {
+        let date = Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).single();
+        Candidate {
+            release: Release::Version(tag.to_string()),
+            prerelease,
+            published: date,
+            artifacts: vec![Artifact {
+                name: "tool".to_string(),
+                url: format!("https://example.com/{}/tool", tag),
+                size: None,
+                updated: date,
+            }],
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn republished_nightlies() {
+        let mut pkg = package(Channel::Nightly, None);
+        pkg.assets_updated = candidate("nightly", true, 2).updated();
+        assert!(pkg.republished(&candidate("nightly", true, 3)));
+        assert!(!pkg.republished(&candidate("nightly", true, 2)));
+        assert!(!pkg.republished(&candidate("nightly", true, 1)));
+        pkg.channel = Channel::Prerelease;
+        assert!(!pkg.republished(&candidate("nightly", true, 3)));
+    }
+}
@@ -0,0 +1,58 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static VERSION: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d+(\.\d+)+").unwrap());
+
+/// Turn a file name into a pattern that survives version bumps by
+/// replacing the release version and anything looking like a version with `*`
+pub fn wildcard(name: &str, version: Option<&str>) -> String {
+    let mut result = name.to_string();
+    if let Some(v) = version.map(|v| v.trim_start_matches('v')) {
+        if !v.is_empty() {
+            result = result.replace(v, "*");
+        }
+    }
+    VERSION.replace_all(&result, "*").to_string()
+}
+
+/// Match a name against a pattern where `*` matches any sequence of characters
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let expr = pattern
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<String>>()
+        .join(".*");
+    Regex::new(&format!("^{}$", expr))
+        .map(|re| re.is_match(name))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_versions() {
+        for (name, version, expected) in [
+            ("rg-13.0.0-x86_64.tar.gz", None, "rg-*-x86_64.tar.gz"),
+            ("tool-v1.2-linux", Some("v1.2"), "tool-v*-linux"),
+            ("tool_2024a-linux", Some("2024a"), "tool_*-linux"),
+            ("tool-linux", Some(""), "tool-linux"),
+            ("mold-2.0/bin/mold", None, "mold-*/bin/mold"),
+        ] {
+            assert_eq!(wildcard(name, version), expected);
+        }
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("rg-*-x86_64.tar.gz", "rg-14.1.0-x86_64.tar.gz"));
+        assert!(matches("bin/mold", "bin/mold"));
+        assert!(matches("*", ""));
+        assert!(!matches("bin/mold", "bin/ld.mold"));
+        assert!(!matches("rg-*.tar.gz", "rg-14.1.0.tar.gz.sha256"));
+        // Regex syntax in names is taken literally
+        assert!(matches("a+b.(x)", "a+b.(x)"));
+        assert!(!matches("a.b", "axb"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::env::consts::{ARCH, OS};
+use std::path::Path;
+
+const OS_TOKENS: [(&str, &[&str]); 6] = [
+    ("linux", &["linux"]),
+    ("macos", &["darwin", "macos", "osx", "apple", "mac"]),
+    ("windows", &["windows", "win", "msvc", "mingw"]),
+    ("freebsd", &["freebsd"]),
+    ("netbsd", &["netbsd"]),
+    ("android", &["android"]),
+];
+
+// Order matters: the first arch with a matching token wins
+const ARCH_TOKENS: [(&str, &[&str]); 8] = [
+    ("x86_64", &["x86_64", "x86-64", "amd64", "x64"]),
+    ("aarch64", &["aarch64", "arm64", "armv8"]),
+    ("arm", &["armv7", "armv7l", "armhf", "armv6", "arm"]),
+    ("x86", &["i686", "i586", "i386", "386", "x86", "32bit"]),
+    ("powerpc64", &["ppc64le", "ppc64", "powerpc64"]),
+    ("s390x", &["s390x"]),
+    ("riscv64", &["riscv64"]),
+    ("mips", &["mips", "mipsel", "mips64"]),
+];
+
+const SKIP_SUFFIXES: [&str; 18] = [
+    ".sha256",
+    ".sha512",
+    ".sha256sum",
+    ".sha512sum",
+    ".md5",
+    ".sig",
+    ".asc",
+    ".minisig",
+    ".pem",
+    ".sbom",
+    ".json",
+    ".txt",
+    ".deb",
+    ".rpm",
+    ".apk",
+    ".msi",
+    ".dmg",
+    ".pkg",
+];
+
+const SKIP_TOKENS: [&str; 5] = ["src", "source", "sources", "checksums", "sha256sums"];
+
+const ARCHIVE_SUFFIXES: [&str; 15] = [
+    ".tar", ".tar.gz", ".tgz", ".tar.bz", ".tar.bz2", ".tbz", ".tar.xz", ".txz", ".tar.zst",
+    ".tzst", ".zip", ".gz", ".xz", ".zst", ".lz4",
+];
+
+/// Rate how well a release asset fits the host, higher is better.
+/// Returns `None` for assets that can not be installed here at all.
+pub fn score(name: &str) -> Option<i32> {
+    let name = name.to_lowercase();
+    if SKIP_SUFFIXES.iter().any(|s| name.ends_with(s))
+        || SKIP_TOKENS.iter().any(|t| has_token(&name, t))
+    {
+        return None;
+    }
+    let mut result = 0;
+
+    match find(&name, &OS_TOKENS) {
+        Some(os) if os == OS => result += 8,
+        Some(_) => return None,
+        None => {}
+    }
+    match find(&name, &ARCH_TOKENS) {
+        Some(arch) if arch == ARCH => result += 4,
+        Some(_) => return None,
+        None => {}
+    }
+    let musl = ["musl", "musleabi", "musleabihf"]
+        .iter()
+        .any(|t| has_token(&name, t));
+    let gnu = ["gnu", "gnueabi", "gnueabihf", "glibc"]
+        .iter()
+        .any(|t| has_token(&name, t));
+    if host_is_musl() {
+        if gnu {
+            return None;
+        }
+        if musl {
+            result += 2;
+        }
+    } else if musl {
+        // Statically linked, runs anywhere
+        result += 2;
+    } else if gnu {
+        result += 1;
+    }
+
+    if ARCHIVE_SUFFIXES.iter().any(|s| name.ends_with(s))
+        || !name.contains('.')
+        || name.ends_with(".appimage")
+    {
+        result += 1;
+    }
+    Some(result)
+}
+
+fn find(name: &str, table: &[(&'static str, &[&str])]) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(_, tokens)| tokens.iter().any(|t| has_token(name, t)))
+        .map(|(key, _)| *key)
+}
+
+// A token must not be glued to other letters, e.g. `arm` in `charm` or `win` in `darwin`.
+// Trailing digits are fine to cover names like `linux64` or `win32`.
+fn has_token(name: &str, token: &str) -> bool {
+    name.match_indices(token).any(|(i, _)| {
+        let before = name[..i].chars().next_back();
+        let after = name[i + token.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric())
+            && !after.is_some_and(|c| c.is_ascii_alphabetic())
+    })
+}
+
+fn host_is_musl() -> bool {
+    if cfg!(target_env = "musl") {
+        return true;
+    }
+    ["/lib", "/usr/lib"].iter().any(|dir| {
+        Path::new(dir)
+            .read_dir()
+            .map(|mut entries| {
+                entries.any(|e| {
+                    e.map(|e| e.file_name().to_string_lossy().starts_with("ld-musl-"))
+                        .unwrap_or(false)
+                })
+            })
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(table: &[(&'static str, &[&'static str])], host: &str, foreign: bool) -> &'static str {
+        table
+            .iter()
+            .find(|(key, _)| (*key == host) != foreign)
+            .map(|(_, tokens)| tokens[0])
+            .unwrap()
+    }
+
+    // Asset name for the host, or for another OS or arch
+    fn name(foreign_os: bool, foreign_arch: bool, suffix: &str) -> String {
+        format!(
+            "tool-1.0-{}-{}{}",
+            token(&ARCH_TOKENS, ARCH, foreign_arch),
+            token(&OS_TOKENS, OS, foreign_os),
+            suffix
+        )
+    }
+
+    #[test]
+    fn score_host() {
+        let host = score(&name(false, false, ".tar.gz")).unwrap();
+        assert!(host > score("tool-1.0.tar.gz").unwrap());
+        assert!(host > score(&name(false, false, ".pdf")).unwrap());
+        assert_eq!(score(&name(true, false, ".tar.gz")), None);
+        assert_eq!(score(&name(false, true, ".tar.gz")), None);
+    }
+
+    #[test]
+    fn score_libc() {
+        let musl = score(&name(false, false, "-musl.tar.gz"));
+        let gnu = score(&name(false, false, "-gnu.tar.gz"));
+        match host_is_musl() {
+            true => assert_eq!(gnu, None),
+            false => assert!(musl.unwrap() > gnu.unwrap()),
+        }
+    }
+
+    #[test]
+    fn score_skips() {
+        for skipped in [
+            name(false, false, ".tar.gz.sha256"),
+            name(false, false, ".tar.gz.sig"),
+            name(false, false, ".tar.gz.asc"),
+            name(false, false, ".deb"),
+            name(false, false, ".rpm"),
+            "tool-1.0-src.tar.gz".to_string(),
+            "tool-1.0-source.zip".to_string(),
+            "SHA256SUMS".to_string(),
+        ] {
+            assert_eq!(score(&skipped), None, "{} was not skipped", skipped);
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(has_token("tool-linux64", "linux"));
+        assert!(has_token("tool-win32.zip", "win"));
+        assert!(has_token("tool_arm.tar.gz", "arm"));
+        assert!(!has_token("tool-darwin", "win"));
+        assert!(!has_token("charm-1.0", "arm"));
+        assert!(!has_token("tool-armhf", "arm"));
+    }
+}
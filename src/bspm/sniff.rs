@@ -0,0 +1,179 @@
+use super::installer::{Archived, Compression};
+
+/// What a download turned out to be
+pub enum Format {
+    Compressed(Compression),
+    Archive(Archived),
+    /// ELF or Mach-O binary, or a script
+    Executable,
+}
+
+impl Format {
+    /// Anything that is not compressed is conclusive about the compression as well
+    pub fn compression(self) -> Compression {
+        match self {
+            Format::Compressed(compression) => compression,
+            _ => Compression::None,
+        }
+    }
+
+    pub fn archive(self) -> Option<Archived> {
+        match self {
+            Format::Archive(archive) => Some(archive),
+            Format::Executable => Some(Archived::None),
+            Format::Compressed(_) => None,
+        }
+    }
+}
+
+/// Recognize a file by its first bytes, 512 are enough for a tar header
+pub fn magic(head: &[u8]) -> Option<Format> {
+    Some(match head {
+        [0x1f, 0x8b, ..] => Format::Compressed(Compression::Gzip),
+        [b'B', b'Z', b'h', ..] => Format::Compressed(Compression::Bzip2),
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Format::Compressed(Compression::Xz),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Format::Compressed(Compression::Zstd),
+        [0x04, 0x22, 0x4d, 0x18, ..] => Format::Compressed(Compression::Lz4),
+        [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => {
+            Format::Archive(Archived::Zip)
+        }
+        [0x7f, b'E', b'L', b'F', ..]
+        | [0xfe, 0xed, 0xfa, 0xce | 0xcf, ..]
+        | [0xce | 0xcf, 0xfa, 0xed, 0xfe, ..]
+        | [0xca, 0xfe, 0xba, 0xbe, ..]
+        | [b'#', b'!', ..] => Format::Executable,
+        _ if head.get(257..262) == Some(b"ustar") => Format::Archive(Archived::Tar),
+        _ => return None,
+    })
+}
+
+/// Recognize a file by the `Content-Type` header, `application/octet-stream` tells nothing
+pub fn content_type(value: &str) -> Option<Format> {
+    let mime = value.split(';').next()?.trim().to_lowercase();
+    Some(match mime.as_str() {
+        "application/gzip" | "application/x-gzip" => Format::Compressed(Compression::Gzip),
+        "application/x-bzip2" => Format::Compressed(Compression::Bzip2),
+        "application/x-xz" => Format::Compressed(Compression::Xz),
+        "application/zstd" => Format::Compressed(Compression::Zstd),
+        "application/zip" | "application/x-zip-compressed" => Format::Archive(Archived::Zip),
+        "application/x-tar" | "application/x-gtar" => Format::Archive(Archived::Tar),
+        "application/x-executable"
+        | "application/x-elf"
+        | "application/x-mach-binary"
+        | "application/x-sharedlib"
+        | "text/x-shellscript" => Format::Executable,
+        _ => return None,
+    })
+}
+
+/// File name from a `Content-Disposition` header like `attachment; filename="tool.tar.gz"`
+pub fn file_name(value: &str) -> Option<String> {
+    let params: Vec<(&str, &str)> = value
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .collect();
+    let name = match params
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("filename*"))
+    {
+        // RFC 5987 `UTF-8''tool.tar.gz`, the name is ASCII in practice
+        Some((_, value)) => value.rsplit('\'').next()?,
+        None => params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("filename"))?
+            .1
+            .trim_matches('"'),
+    };
+    let name = name.rsplit(['/', '\\']).next()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// URL path without query string and fragment, for suffix matching
+pub fn url_path(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_header() -> Vec<u8> {
+        let mut head = vec![0; 512];
+        head[257..262].copy_from_slice(b"ustar");
+        head
+    }
+
+    #[test]
+    fn magic_bytes() {
+        for (head, compression) in [
+            (&[0x1f, 0x8b, 0x08][..], Compression::Gzip),
+            (b"BZh91AY", Compression::Bzip2),
+            (&[0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00], Compression::Xz),
+            (&[0x28, 0xb5, 0x2f, 0xfd, 0x00], Compression::Zstd),
+            (&[0x04, 0x22, 0x4d, 0x18, 0x64], Compression::Lz4),
+        ] {
+            assert!(matches!(magic(head), Some(Format::Compressed(c)) if c == compression));
+        }
+        assert!(matches!(
+            magic(b"PK\x03\x04rest"),
+            Some(Format::Archive(Archived::Zip))
+        ));
+        assert!(matches!(
+            magic(&tar_header()),
+            Some(Format::Archive(Archived::Tar))
+        ));
+        for executable in [
+            &b"\x7fELF\x02\x01"[..],
+            &[0xcf, 0xfa, 0xed, 0xfe, 0x07],
+            b"#!/bin/sh\n",
+        ] {
+            assert!(matches!(magic(executable), Some(Format::Executable)));
+        }
+        assert!(magic(b"<html>").is_none());
+        assert!(magic(&tar_header()[..200]).is_none());
+        assert!(magic(b"").is_none());
+    }
+
+    #[test]
+    fn content_types() {
+        assert!(matches!(
+            content_type("application/x-gzip; charset=binary"),
+            Some(Format::Compressed(Compression::Gzip))
+        ));
+        assert!(matches!(
+            content_type("Application/Zip"),
+            Some(Format::Archive(Archived::Zip))
+        ));
+        assert!(matches!(
+            content_type("application/x-executable"),
+            Some(Format::Executable)
+        ));
+        assert!(content_type("application/octet-stream").is_none());
+        assert!(content_type("").is_none());
+    }
+
+    #[test]
+    fn content_disposition() {
+        for (value, expected) in [
+            ("attachment; filename=\"tool.tar.gz\"", Some("tool.tar.gz")),
+            ("attachment; FILENAME=tool.zip", Some("tool.zip")),
+            (
+                "attachment; filename=\"x\"; filename*=UTF-8''tool-1.0.tar.xz",
+                Some("tool-1.0.tar.xz"),
+            ),
+            ("attachment; filename=\"../../etc/passwd\"", Some("passwd")),
+            (
+                "attachment; filename=\"C:\\\\dl\\\\tool.exe\"",
+                Some("tool.exe"),
+            ),
+            ("attachment; filename=\"\"", None),
+            ("inline", None),
+        ] {
+            assert_eq!(file_name(value).as_deref(), expected, "{}", value);
+        }
+        assert_eq!(
+            url_path("https://x/tool.tar.gz?token=1#top"),
+            "https://x/tool.tar.gz"
+        );
+    }
+}
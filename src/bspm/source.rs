@@ -0,0 +1,453 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use chrono::prelude::*;
+use serde_yaml::{Mapping, Value};
+
+use super::{
+    discover::Discover,
+    forge,
+    gitea::Gitea,
+    github::GitHub,
+    gitlab::GitLab,
+    installer::Credentials,
+    package::{Channel, Package, Release},
+    template,
+    ui::context,
+    verify::Checksum,
+    version, Forge, Settings, GITHUB,
+};
+
+/// Where a package is released, stored in the config file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PackageSource {
+    /// Direct download URL
+    Url(String),
+    /// `owner/repo` on github.com or `host/owner/repo` on GitHub Enterprise
+    GitHub(String),
+    /// `host/group/project`, groups may be nested
+    GitLab(String),
+    /// `host/owner/repo` on Gitea or Forgejo
+    Gitea(String),
+    /// Download URL, may have placeholders. `{version}` is taken from the pin or the releases of
+    /// another source
+    Template {
+        url: String,
+        versions: Option<Box<PackageSource>>,
+    },
+    /// Versions read from a JSON document at a JSON pointer
+    Json { url: String, pointer: String },
+    /// Versions read from a web page with a regex
+    Regex { url: String, pattern: String },
+}
+
+impl PackageSource {
+    /// Tell direct download URLs from repositories on the known kinds of servers
+    pub fn parse(url: &str, settings: &Settings) -> anyhow::Result<PackageSource> {
+        if template::is_template(url) {
+            return Ok(PackageSource::Template {
+                url: url.to_string(),
+                versions: None,
+            });
+        }
+        Ok(match forge(url, settings)? {
+            Some(Forge::GitHub) => PackageSource::GitHub(url.to_string()),
+            Some(Forge::GitLab) => PackageSource::GitLab(url.to_string()),
+            Some(Forge::Gitea) => PackageSource::Gitea(url.to_string()),
+            None => PackageSource::Url(url.to_string()),
+        })
+    }
+
+    /// Whether the source has tagged releases to pin, constrain or follow
+    pub fn versioned(&self) -> bool {
+        match self {
+            PackageSource::Url(_) => false,
+            PackageSource::Template { url, versions } => {
+                template::versioned(url) || versions.is_some()
+            }
+            _ => true,
+        }
+    }
+
+    pub fn backend(&self, settings: &Settings) -> anyhow::Result<Box<dyn Source>> {
+        // Only GitHub repositories may omit the host
+        let split = |path: &str| match path.split_once('/') {
+            Some((host, repo)) if repo.contains('/') => (host.to_string(), repo.to_string()),
+            _ => (GITHUB.to_string(), path.to_string()),
+        };
+        Ok(match self {
+            PackageSource::Url(url) => Box::new(Direct { url: url.clone() }),
+            PackageSource::GitHub(path) => {
+                let (host, repo) = split(path);
+                Box::new(GitHub::new(&host, repo, settings))
+            }
+            PackageSource::GitLab(path) => {
+                let (host, project) = split(path);
+                Box::new(GitLab::new(&host, project, settings))
+            }
+            PackageSource::Gitea(path) => {
+                let (host, repo) = split(path);
+                Box::new(Gitea::new(&host, repo, settings))
+            }
+            PackageSource::Template { url, .. } if !self.versioned() => Box::new(Direct {
+                url: template::expand(url, None)?,
+            }),
+            PackageSource::Template { url, versions } => Box::new(Template {
+                url: url.clone(),
+                versions: versions
+                    .as_ref()
+                    .map(|versions| versions.backend(settings))
+                    .transpose()?,
+            }),
+            PackageSource::Json { url, pointer } => Box::new(Discover::json(url, pointer)?),
+            PackageSource::Regex { url, pattern } => Box::new(Discover::regex(url, pattern)?),
+        })
+    }
+}
+
+impl std::fmt::Display for PackageSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageSource::Url(s)
+            | PackageSource::GitHub(s)
+            | PackageSource::GitLab(s)
+            | PackageSource::Gitea(s)
+            | PackageSource::Template {
+                url: s,
+                versions: None,
+            } => write!(f, "{}", s),
+            PackageSource::Template {
+                url,
+                versions: Some(versions),
+            } => write!(f, "{} (versions from {})", url, versions),
+            PackageSource::Json { url, pointer } => write!(f, "{} at {}", url, pointer),
+            PackageSource::Regex { url, pattern } => write!(f, "{} matching {}", url, pattern),
+        }
+    }
+}
+
+/// A place packages are released at
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// What the package is treated as, shown to the user
+    fn describe(&self) -> &'static str;
+
+    async fn releases(&self) -> anyhow::Result<Vec<Candidate>>;
+
+    async fn release(&self, tag: &str) -> anyhow::Result<Candidate>;
+
+    /// Credentials needed to download artifacts
+    fn credentials(&self) -> Option<Credentials> {
+        None
+    }
+
+    /// The pinned release if there is one, otherwise the best release of the channel
+    async fn latest(&self, pkg: &Package) -> anyhow::Result<Candidate> {
+        match &pkg.pin {
+            Some(tag) => self.release(tag).await,
+            None => select(self.releases().await?, pkg).await,
+        }
+    }
+}
+
+/// A release offered by a source
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub release: Release,
+    pub prerelease: bool,
+    pub published: Option<DateTime<Utc>>,
+    pub artifacts: Vec<Artifact>,
+    /// Known up front, otherwise looked up in the checksum files among the artifacts
+    pub checksum: Option<Checksum>,
+}
+
+impl Candidate {
+    /// Newest artifact upload, nightlies re-publish artifacts under the same tag
+    pub fn updated(&self) -> Option<DateTime<Utc>> {
+        self.artifacts.iter().filter_map(|a| a.updated).max()
+    }
+}
+
+/// A downloadable file of a release
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub name: String,
+    pub url: String,
+    pub size: Option<u64>,
+    pub updated: Option<DateTime<Utc>>,
+}
+
+async fn select(releases: Vec<Candidate>, pkg: &Package) -> anyhow::Result<Candidate> {
+    let constraint = version::constraint(pkg.constraint.as_deref())?;
+    let releases: Vec<Candidate> = releases
+        .into_iter()
+        .filter(|r| pkg.channel != Channel::Stable || !r.prerelease)
+        .collect();
+    let newest = |releases: Vec<Candidate>| {
+        releases
+            .into_iter()
+            .max_by_key(|r| r.published)
+            .ok_or_else(|| anyhow!("No releases found"))
+    };
+    if pkg.channel == Channel::Nightly {
+        return newest(releases);
+    }
+    let matching = |v: &semver::Version| match pkg.channel {
+        Channel::Prerelease => constraint.matches(&semver::Version::new(v.major, v.minor, v.patch)),
+        _ => constraint.matches(v),
+    };
+    let highest = releases
+        .iter()
+        .filter_map(|r| Some((version::parse(&r.release.to_string())?, r)))
+        .filter(|(v, _)| matching(v))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, r)| r.clone());
+    match (highest, &pkg.constraint) {
+        (Some(release), _) => Ok(release),
+        (None, Some(constraint)) => bail!("No release satisfies `{}`", constraint),
+        (None, None) => {
+            context("🪐", &pkg.name)
+                .await
+                .notify("No semver release tags found, using the latest release")
+                .await;
+            newest(releases)
+        }
+    }
+}
+
+/// A direct download URL, every download counts as a new release
+pub struct Direct {
+    url: String,
+}
+
+#[async_trait]
+impl Source for Direct {
+    fn describe(&self) -> &'static str {
+        "direct download"
+    }
+
+    async fn releases(&self) -> anyhow::Result<Vec<Candidate>> {
+        Ok(vec![self.candidate(None)])
+    }
+
+    async fn release(&self, _tag: &str) -> anyhow::Result<Candidate> {
+        bail!("Direct downloads have no tagged releases")
+    }
+
+    async fn latest(&self, pkg: &Package) -> anyhow::Result<Candidate> {
+        Ok(self.candidate(pkg.installer.checksum.clone()))
+    }
+}
+
+impl Direct {
+    fn candidate(&self, checksum: Option<Checksum>) -> Candidate {
+        Candidate {
+            release: Release::Dated(Utc::now()),
+            prerelease: false,
+            published: None,
+            artifacts: vec![artifact(&self.url)],
+            checksum,
+        }
+    }
+}
+
+/// A URL template filled in with the versions of another source or the pinned version
+pub struct Template {
+    url: String,
+    versions: Option<Box<dyn Source>>,
+}
+
+#[async_trait]
+impl Source for Template {
+    fn describe(&self) -> &'static str {
+        "URL template"
+    }
+
+    async fn releases(&self) -> anyhow::Result<Vec<Candidate>> {
+        let versions = match &self.versions {
+            Some(versions) => versions,
+            None => {
+                bail!("URL templates need a pinned version or a repository to take versions from")
+            }
+        };
+        versions
+            .releases()
+            .await?
+            .into_iter()
+            .map(|candidate| self.fill(candidate))
+            .collect()
+    }
+
+    async fn release(&self, tag: &str) -> anyhow::Result<Candidate> {
+        self.fill(Candidate {
+            release: Release::Version(tag.to_string()),
+            prerelease: false,
+            published: None,
+            artifacts: Vec::new(),
+            checksum: None,
+        })
+    }
+}
+
+impl Template {
+    fn fill(&self, candidate: Candidate) -> anyhow::Result<Candidate> {
+        let url = template::expand(&self.url, Some(&candidate.release.to_string()))?;
+        Ok(Candidate {
+            artifacts: vec![artifact(&url)],
+            checksum: None,
+            ..candidate
+        })
+    }
+}
+
+fn artifact(url: &str) -> Artifact {
+    Artifact {
+        name: url.rsplit('/').next().unwrap_or(url).to_string(),
+        url: url.to_string(),
+        size: None,
+        updated: None,
+    }
+}
+
+/// Move the `github`, `gitlab` and `gitea` fields of old config files into `source`
+pub fn migrate(config: &mut Value) -> bool {
+    let packages = match config
+        .as_mapping_mut()
+        .and_then(|c| c.get_mut(&Value::from("packages")))
+        .and_then(Value::as_sequence_mut)
+    {
+        Some(packages) => packages,
+        None => return false,
+    };
+    let mut migrated = false;
+    for pkg in packages.iter_mut().filter_map(Value::as_mapping_mut) {
+        if pkg.contains_key(&Value::from("source")) {
+            continue;
+        }
+        let mut source = Mapping::new();
+        for (field, kind) in [
+            ("github", "GitHub"),
+            ("gitlab", "GitLab"),
+            ("gitea", "Gitea"),
+        ] {
+            if let Some(Value::String(path)) = pkg.remove(&Value::from(field)) {
+                source.insert(Value::from(kind), Value::from(path));
+            }
+        }
+        if source.is_empty() {
+            let url = pkg
+                .get(&Value::from("installer"))
+                .and_then(|i| i.get("url"))
+                .cloned()
+                .unwrap_or(Value::Null);
+            source.insert(Value::from("Url"), url);
+        }
+        pkg.insert(Value::from("source"), Value::Mapping(source));
+        migrated = true;
+    }
+    migrated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bspm::package::tests::{candidate, package};
+    use crate::bspm::Bspm;
+
+    // Config as written before packages had a `source`
+    const OLD_CONFIG: &str = r#"
+packages:
+  - name: rg
+    installer:
+      url: "https://github.com/BurntSushi/ripgrep/releases/download/14.1.0/rg.tar.gz"
+      path: /home/me/.local/bin/rg
+      compression: Gzip
+      archive: Tar
+    release:
+      Version: 14.1.0
+    last_update: ~
+    github: BurntSushi/ripgrep
+  - name: tool
+    installer:
+      url: "https://example.com/tool"
+      path: /home/me/.local/bin/tool
+      compression: ~
+      archive: ~
+    release:
+      Dated: "2021-01-02T03:04:05Z"
+    last_update: ~
+    github: ~
+"#;
+
+    #[test]
+    fn migrate_old_config() {
+        let mut config: Value = serde_yaml::from_str(OLD_CONFIG).unwrap();
+        assert!(migrate(&mut config));
+        let bspm: Bspm = serde_yaml::from_value(config.clone()).unwrap();
+        assert_eq!(
+            bspm.packages[0].source,
+            PackageSource::GitHub("BurntSushi/ripgrep".to_string())
+        );
+        assert_eq!(
+            bspm.packages[1].source,
+            PackageSource::Url("https://example.com/tool".to_string())
+        );
+        assert_eq!(
+            bspm.packages[0].release.as_ref().unwrap().to_string(),
+            "14.1.0"
+        );
+        // Migrated configs are left alone
+        assert!(!migrate(&mut config));
+        let mut empty: Value = serde_yaml::from_str("settings: {}").unwrap();
+        assert!(!migrate(&mut empty));
+    }
+
+    #[test]
+    fn select_releases() {
+        let releases = || {
+            vec![
+                candidate("v0.9.0", false, 3),
+                candidate("v1.0.0", false, 1),
+                candidate("v1.1.0-rc.1", true, 2),
+                candidate("nightly", true, 4),
+            ]
+        };
+        smol::block_on(async {
+            for (channel, constraint, expected) in [
+                (Channel::Stable, None, "v1.0.0"),
+                (Channel::Stable, Some("<1.0"), "v0.9.0"),
+                (Channel::Prerelease, None, "v1.1.0-rc.1"),
+                // Prereleases are compared without their pre part, `^1.0` takes `1.1.0-rc.1`
+                (Channel::Prerelease, Some("^1.0"), "v1.1.0-rc.1"),
+                (Channel::Prerelease, Some("<1.1"), "v1.0.0"),
+                (Channel::Nightly, None, "nightly"),
+                (Channel::Nightly, Some("^0.9"), "nightly"),
+            ] {
+                let pkg = package(channel, constraint);
+                let selected = select(releases(), &pkg).await.unwrap();
+                assert_eq!(
+                    selected.release.to_string(),
+                    expected,
+                    "{:?} {:?}",
+                    channel,
+                    constraint
+                );
+            }
+            let pkg = package(Channel::Stable, Some("^2"));
+            assert!(select(releases(), &pkg).await.is_err());
+            // Without any semver tag the newest release is taken
+            let untagged = || {
+                vec![
+                    candidate("latest", false, 2),
+                    candidate("build-a", false, 3),
+                    candidate("snapshot", true, 4),
+                ]
+            };
+            let pkg = package(Channel::Stable, None);
+            let selected = select(untagged(), &pkg).await.unwrap();
+            assert_eq!(selected.release.to_string(), "build-a");
+            let pkg = package(Channel::Stable, Some("^1"));
+            assert!(select(untagged(), &pkg).await.is_err());
+            assert!(select(Vec::new(), &pkg).await.is_err());
+        })
+    }
+}
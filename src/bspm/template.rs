@@ -0,0 +1,99 @@
+use std::env::consts::{ARCH, OS};
+
+use anyhow::{anyhow, bail};
+
+/// URLs with placeholders like `https://dl.example.com/tool/{version}/tool_{os}_{arch}.tar.gz`
+pub fn is_template(url: &str) -> bool {
+    url.contains('{') && url.contains('}')
+}
+
+/// Templates without a version placeholder always point at the latest download
+pub fn versioned(template: &str) -> bool {
+    template.contains("{version}") || template.contains("{tag}")
+}
+
+/// Fill in the placeholders, `{version}` is the release tag without a `v` prefix
+pub fn expand(template: &str, tag: Option<&str>) -> anyhow::Result<String> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| anyhow!("Unclosed placeholder in {}", template))?;
+        result.push_str(&rest[..start]);
+        let value = match (&rest[start + 1..end], tag) {
+            ("version", Some(tag)) => tag.strip_prefix('v').unwrap_or(tag),
+            ("tag", Some(tag)) => tag,
+            ("version" | "tag", None) => bail!("No version to fill into {}", template),
+            ("os", _) => OS,
+            ("arch", _) => ARCH,
+            ("goos", _) => goos(),
+            ("goarch", _) => goarch(),
+            (other, _) => bail!("Unknown placeholder `{{{}}}` in {}", other, template),
+        };
+        result.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Fail early on templates that can never be expanded
+pub fn check(template: &str) -> anyhow::Result<()> {
+    expand(template, Some("v0.0.0")).map(|_| ())
+}
+
+// Many vendors name their downloads after Go's GOOS and GOARCH
+fn goos() -> &'static str {
+    match OS {
+        "macos" => "darwin",
+        os => os,
+    }
+}
+
+fn goarch() -> &'static str {
+    match ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        "powerpc64" => "ppc64",
+        arch => arch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_placeholders() {
+        let template = "https://dl.example.com/{tag}/tool_{version}_{os}_{arch}.tar.gz";
+        assert_eq!(
+            expand(template, Some("v1.2.3")).unwrap(),
+            format!(
+                "https://dl.example.com/v1.2.3/tool_1.2.3_{}_{}.tar.gz",
+                OS, ARCH
+            )
+        );
+        assert_eq!(
+            expand("tool-{goos}-{goarch}", None).unwrap(),
+            format!("tool-{}-{}", goos(), goarch())
+        );
+        assert_eq!(expand("no placeholders", None).unwrap(), "no placeholders");
+        assert!(expand("tool-{version}", None).is_err());
+        assert!(expand("tool-{platform}", Some("1.0")).is_err());
+        assert!(expand("tool-{version", Some("1.0")).is_err());
+    }
+
+    #[test]
+    fn templates() {
+        assert!(is_template("https://x/{version}/tool"));
+        assert!(!is_template("https://x/tool"));
+        assert!(versioned("https://x/{tag}/tool"));
+        assert!(!versioned("https://x/latest/tool-{os}"));
+        assert!(check("https://x/{version}/tool-{goarch}").is_ok());
+        assert!(check("https://x/{nope}").is_err());
+    }
+}
@@ -8,8 +8,12 @@ use progress_string::*;
 use smol::{self, Task};
 use std::cmp::min;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use termion::{color, cursor, style};
 
+static INTERACTIVE: Lazy<AtomicBool> =
+    Lazy::new(|| AtomicBool::new(termion::is_tty(&std::io::stdin())));
+
 static CONTEXT: Lazy<Mutex<Context>> = Lazy::new(|| {
     let ui = UI::new();
     let result = ui.context(String::new());
@@ -23,6 +27,16 @@ pub async fn context(prefix: &str, name: &str) -> Context {
     result
 }
 
+/// Whether questions can be asked on STDIN.
+/// Defaults to `false` if STDIN is not a TTY.
+pub fn interactive() -> bool {
+    INTERACTIVE.load(Ordering::SeqCst)
+}
+
+pub fn set_interactive(value: bool) {
+    INTERACTIVE.store(value, Ordering::SeqCst)
+}
+
 pub struct UI {
     stdout_send: Sender<(String, Message)>,
     stdout_recv: Receiver<(String, Message)>,
@@ -94,6 +108,10 @@ impl UI {
             style::Bold,
             style::Reset,
         );
+        // Without a terminal there is no screen to draw, just print the messages line by line
+        if !termion::is_tty(&std::io::stdout()) {
+            return self.plainloop().await;
+        }
         self.draw(termion::clear::All.to_string()).await?;
 
         while let Ok((context, msg)) = self.stdout_recv.recv().await {
@@ -136,6 +154,26 @@ impl UI {
         Err(anyhow!("UI failed to receive next message"))
     }
 
+    async fn plainloop(self) -> anyhow::Result<()> {
+        while let Ok((context, msg)) = self.stdout_recv.recv().await {
+            match msg {
+                Message::Quit(tx) => {
+                    tx.send(()).await?;
+                    return Ok(());
+                }
+                Message::Notification(msg) => {
+                    self.draw(format!("{} {}\n", context, msg.trim())).await?
+                }
+                Message::Question(msg) => {
+                    self.read_line(&format!("{} {}", context, msg.trim()))
+                        .await?
+                }
+                Message::Progress(_) => {}
+            }
+        }
+        Err(anyhow!("UI failed to receive next message"))
+    }
+
     fn render(self) -> Task<()> {
         smol::spawn(async move {
             self.mainloop().await.expect("UI render failure");
@@ -143,6 +181,30 @@ impl UI {
     }
 }
 
+// Numbers in `min..max` with an optional file name each, repeated numbers are dropped
+fn parse_picks(line: &str, min: usize, max: usize) -> Option<Vec<(usize, Option<String>)>> {
+    let mut picks: Vec<(usize, Option<String>)> = Vec::new();
+    for pick in line.split([' ', ',']).filter(|x| !x.is_empty()) {
+        let (number, name) = match pick.split_once('=') {
+            Some((number, name)) => (number, Some(name)),
+            None => (pick, None),
+        };
+        let number = number
+            .parse::<usize>()
+            .ok()
+            .filter(|x| *x >= min && *x < max)?;
+        if name.is_some_and(|name| {
+            name.is_empty() || name.contains('/') || name == "." || name == ".."
+        }) {
+            return None;
+        }
+        if !picks.iter().any(|(other, _)| *other == number) {
+            picks.push((number, name.map(str::to_string)));
+        }
+    }
+    Some(picks).filter(|picks| !picks.is_empty())
+}
+
 fn fmt_msg(context: String, message: String) -> String {
     format!(
         "{}{}{}{}{} {}",
@@ -161,11 +223,11 @@ fn fmt_bar(nth: usize, msg: &str, pbar: &Bar) -> String {
         cursor::Goto(1, nth as u16 + 1),
         termion::clear::CurrentLine,
         style::Italic,
-        msg[..min(msg.len(), 64)].to_string(),
+        &msg[..min(msg.len(), 64)],
         style::Reset,
         style::Bold,
         color::Fg(termion::color::Blue),
-        pbar.to_string(),
+        pbar,
         style::Reset,
     )
 }
@@ -205,14 +267,47 @@ impl Context {
         rx.recv().await
     }
 
-    pub async fn ask(&self, msg: &str) -> anyhow::Result<String, RecvError> {
+    pub async fn ask(&self, msg: &str) -> anyhow::Result<String> {
+        if !interactive() {
+            return Err(anyhow!(
+                "Can not ask `{}` in non-interactive mode",
+                msg.trim()
+            ));
+        }
         self.send(Message::Question(msg.to_string())).await;
-        self.stdin.recv().await
+        Ok(self.stdin.recv().await?)
     }
 
-    pub async fn ask_number(&self, min: usize, max: usize, msg: &str) -> anyhow::Result<usize> {
-        while let Ok(input) = self.ask(msg).await {
+    pub async fn ask_number(
+        &self,
+        min: usize,
+        max: usize,
+        default: Option<usize>,
+        msg: &str,
+    ) -> anyhow::Result<usize> {
+        if !interactive() {
+            return match default {
+                Some(d) => {
+                    self.notify(&format!("{} {} (non-interactive default)", msg, d))
+                        .await;
+                    Ok(d)
+                }
+                None => Err(anyhow!(
+                    "Can not decide `{}` in non-interactive mode",
+                    msg.trim()
+                )),
+            };
+        }
+        let msg = match default {
+            Some(d) => format!("{} [{}]", msg, d),
+            None => msg.to_string(),
+        };
+        loop {
+            let input = self.ask(&msg).await?;
             let line = input.trim();
+            if let (true, Some(d)) = (line.is_empty(), default) {
+                return Ok(d);
+            }
             if let Ok(x) = line.parse::<usize>() {
                 if x >= min && x < max {
                     return Ok(x);
@@ -220,7 +315,38 @@ impl Context {
             }
             self.notify(&format!("Invalid input: {:?}", &line)).await;
         }
-        Err(anyhow!("Failed to receive from STDIN"))
+    }
+
+    /// Like `ask_number`, but any number of choices separated by spaces or commas, each one may
+    /// be given a name like `3=tool`
+    pub async fn ask_picks(
+        &self,
+        min: usize,
+        max: usize,
+        default: Option<usize>,
+        msg: &str,
+    ) -> anyhow::Result<Vec<(usize, Option<String>)>> {
+        if !interactive() {
+            return self
+                .ask_number(min, max, default, msg)
+                .await
+                .map(|d| vec![(d, None)]);
+        }
+        let msg = match default {
+            Some(d) => format!("{} [{}]", msg, d),
+            None => msg.to_string(),
+        };
+        loop {
+            let input = self.ask(&msg).await?;
+            let line = input.trim();
+            if let (true, Some(d)) = (line.is_empty(), default) {
+                return Ok(vec![(d, None)]);
+            }
+            if let Some(picks) = parse_picks(line, min, max) {
+                return Ok(picks);
+            }
+            self.notify(&format!("Invalid input: {:?}", &line)).await;
+        }
     }
 
     pub async fn progress(&self, current: u64, total: u64, msg: &str) {
@@ -235,3 +361,19 @@ impl Context {
             .expect("internal channel error")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks() {
+        assert_eq!(
+            parse_picks("4 3, 2=ld 3", 0, 5),
+            Some(vec![(4, None), (3, None), (2, Some("ld".to_string()))])
+        );
+        for line in ["", "5", "x", "1=", "1=a/b", "1=..", "-1"] {
+            assert_eq!(parse_picks(line, 0, 5), None, "{}", line);
+        }
+    }
+}
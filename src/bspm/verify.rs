@@ -0,0 +1,289 @@
+use anyhow::{bail, Context};
+use sha2::{Digest, Sha256, Sha512};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Checksum {
+    Sha256(String),
+    Sha512(String),
+}
+
+impl Checksum {
+    /// Guess the algorithm from the length of a hex digest
+    pub fn from_hex(hex: &str) -> Option<Checksum> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        match hex.len() {
+            64 => Some(Checksum::Sha256(hex.to_lowercase())),
+            128 => Some(Checksum::Sha512(hex.to_lowercase())),
+            _ => None,
+        }
+    }
+
+    pub fn verify(&self, digests: &Digests) -> anyhow::Result<()> {
+        let (expected, actual) = match self {
+            Checksum::Sha256(v) => (v, digests.sha256()),
+            Checksum::Sha512(v) => (v, digests.sha512()),
+        };
+        if expected.to_lowercase() != actual {
+            bail!(
+                "Checksum mismatch\nExpected: {}\nActual: {}",
+                expected,
+                actual
+            )
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Checksum::Sha256(v) => write!(f, "sha256:{}", v),
+            Checksum::Sha512(v) => write!(f, "sha512:{}", v),
+        }
+    }
+}
+
+/// Hashes everything that gets downloaded
+#[derive(Default, Clone)]
+pub struct Digests {
+    sha256: Sha256,
+    sha512: Sha512,
+}
+
+impl Digests {
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.sha512.update(data);
+    }
+
+    pub fn sha256(&self) -> String {
+        hex::encode(self.sha256.clone().finalize())
+    }
+
+    pub fn sha512(&self) -> String {
+        hex::encode(self.sha512.clone().finalize())
+    }
+}
+
+/// Find the checksum for `name` in a checksum file.
+/// Understands `sha256sum` style (`<hex>  <name>`), BSD style (`SHA256 (<name>) = <hex>`)
+/// and files containing nothing but the digest.
+pub fn parse_checksums(text: &str, name: &str) -> Option<Checksum> {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    for line in &lines {
+        let mut parts = line.split_whitespace();
+        let first = parts.next()?;
+        if let Some(rest) = line
+            .strip_prefix("SHA256 (")
+            .or_else(|| line.strip_prefix("SHA512 ("))
+        {
+            if let Some((file, hex)) = rest.split_once(") = ") {
+                if file == name {
+                    return Checksum::from_hex(hex.trim());
+                }
+            }
+            continue;
+        }
+        let file = match parts.next() {
+            Some(file) => file.trim_start_matches('*'),
+            None => continue,
+        };
+        let file = file.rsplit('/').next().unwrap_or(file);
+        if file == name {
+            return Checksum::from_hex(first);
+        }
+    }
+    match lines.as_slice() {
+        [single] if single.split_whitespace().count() == 1 => Checksum::from_hex(single),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SignatureKind {
+    Minisign,
+    Signify,
+}
+
+/// A trusted public key used to check detached signatures of downloads
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Signature {
+    pub kind: SignatureKind,
+    pub public_key: String,
+}
+
+impl Signature {
+    /// Suffix of the detached signature file published next to the download
+    pub fn suffix(&self) -> &'static str {
+        match self.kind {
+            SignatureKind::Minisign => ".minisig",
+            SignatureKind::Signify => ".sig",
+        }
+    }
+
+    pub fn verify(&self, data: &[u8], signature: &str) -> anyhow::Result<()> {
+        match self.kind {
+            SignatureKind::Minisign => {
+                let key = minisign_verify::PublicKey::from_base64(key_line(&self.public_key))
+                    .context("Invalid minisign public key")?;
+                let signature = minisign_verify::Signature::decode(signature)
+                    .context("Invalid minisign signature")?;
+                key.verify(data, &signature, true)
+                    .context("Bad minisign signature")
+            }
+            SignatureKind::Signify => {
+                let key = base64::decode(key_line(&self.public_key))
+                    .context("Invalid signify public key")?;
+                let signature =
+                    base64::decode(key_line(signature)).context("Invalid signify signature")?;
+                if key.len() != 42 || !key.starts_with(b"Ed") {
+                    bail!("Invalid signify public key")
+                }
+                if signature.len() != 74 || !signature.starts_with(b"Ed") {
+                    bail!("Invalid signify signature")
+                }
+                if key[2..10] != signature[2..10] {
+                    bail!("Signature was made with a different signify key")
+                }
+                let key = ed25519_compact::PublicKey::from_slice(&key[10..])?;
+                let signature = ed25519_compact::Signature::from_slice(&signature[10..])?;
+                key.verify(data, &signature)
+                    .context("Bad signify signature")
+            }
+        }
+    }
+}
+
+// Keys and signatures may be given with or without their `untrusted comment:` line
+fn key_line(text: &str) -> &str {
+    text.lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with("untrusted comment:"))
+        .unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_compact::{KeyPair, Seed};
+
+    use super::*;
+
+    const SHA256: &str = "a9352f3c0e3ff3f1a3bbfc5d7d5c9d7ba3d2c5de3aeb8ee6d0c0bbd0b1b3a1f2";
+
+    #[test]
+    fn checksum_files() {
+        let sha512 = "ab".repeat(64);
+        let text = format!(
+            "{}  tool-linux.tar.gz\n{} *dist/tool-macos.tar.gz\nSHA512 (tool.zip) = {}\n",
+            SHA256,
+            SHA256.to_uppercase(),
+            sha512
+        );
+        let sha256 = Some(Checksum::Sha256(SHA256.to_string()));
+        assert_eq!(parse_checksums(&text, "tool-linux.tar.gz"), sha256);
+        assert_eq!(parse_checksums(&text, "tool-macos.tar.gz"), sha256);
+        assert_eq!(
+            parse_checksums(&text, "tool.zip"),
+            Some(Checksum::Sha512(sha512))
+        );
+        assert_eq!(parse_checksums(&text, "tool"), None);
+        assert_eq!(parse_checksums(&format!("{}\n", SHA256), "tool"), sha256);
+        assert_eq!(parse_checksums("not a checksum", "tool"), None);
+    }
+
+    #[test]
+    fn checksum_verify() {
+        let mut digests = Digests::default();
+        digests.update(b"blindspot");
+        Checksum::from_hex(&digests.sha256())
+            .unwrap()
+            .verify(&digests)
+            .unwrap();
+        Checksum::from_hex(&digests.sha512().to_uppercase())
+            .unwrap()
+            .verify(&digests)
+            .unwrap();
+        assert!(Checksum::Sha256(SHA256.to_string())
+            .verify(&digests)
+            .is_err());
+        assert_eq!(Checksum::from_hex("abc"), None);
+        assert_eq!(Checksum::from_hex(&"x".repeat(64)), None);
+    }
+
+    fn key_pair(seed: u8) -> KeyPair {
+        KeyPair::from_seed(Seed::new([seed; 32]))
+    }
+
+    // Keys and signatures in the format of `signify -G` and `minisign -G`, they share the layout
+    fn encode(key_id: &[u8; 8], bytes: &[u8]) -> String {
+        base64::encode([b"Ed".as_ref(), key_id, bytes].concat())
+    }
+
+    fn signify(seed: u8, data: &[u8]) -> (Signature, String) {
+        let pair = key_pair(seed);
+        let key_id = [seed; 8];
+        let signature = Signature {
+            kind: SignatureKind::Signify,
+            public_key: format!(
+                "untrusted comment: signify public key\n{}\n",
+                encode(&key_id, pair.pk.as_ref())
+            ),
+        };
+        let text = format!(
+            "untrusted comment: verify with key.pub\n{}\n",
+            encode(&key_id, pair.sk.sign(data, None).as_ref())
+        );
+        (signature, text)
+    }
+
+    fn minisign(seed: u8, data: &[u8]) -> (Signature, String) {
+        let pair = key_pair(seed);
+        let key_id = [seed; 8];
+        let signature = Signature {
+            kind: SignatureKind::Minisign,
+            public_key: encode(&key_id, pair.pk.as_ref()),
+        };
+        let data_signature = pair.sk.sign(data, None);
+        let comment = "timestamp:0";
+        let global = pair
+            .sk
+            .sign([data_signature.as_ref(), comment.as_bytes()].concat(), None);
+        let text = format!(
+            "untrusted comment: minisign signature\n{}\ntrusted comment: {}\n{}\n",
+            encode(&key_id, data_signature.as_ref()),
+            comment,
+            base64::encode(global.as_ref())
+        );
+        (signature, text)
+    }
+
+    #[test]
+    fn signatures() {
+        for sign in [signify, minisign] {
+            let (signature, text) = sign(1, b"blindspot");
+            signature.verify(b"blindspot", &text).unwrap();
+            assert!(signature.verify(b"blindspot!", &text).is_err());
+            let (_, other) = sign(2, b"blindspot");
+            assert!(signature.verify(b"blindspot", &other).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_keys() {
+        for kind in [SignatureKind::Signify, SignatureKind::Minisign] {
+            let signature = Signature {
+                kind,
+                public_key: "untrusted comment: broken\nRWQ=".to_string(),
+            };
+            assert!(signature.verify(b"", "").is_err());
+        }
+        assert_eq!(key_line("\nuntrusted comment: x\n  RWQ  \n"), "RWQ");
+        assert_eq!(key_line("RWQ"), "RWQ");
+    }
+}
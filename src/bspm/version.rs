@@ -0,0 +1,98 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use semver::{Prerelease, Version, VersionReq};
+
+static LOOSE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\d+)(?:\.(\d+))?(?:\.(\d+))?(?:[-.]?([0-9A-Za-z][0-9A-Za-z.-]*))?$").unwrap()
+});
+
+// Dates like `2024-01-05` count as `2024.1.5`, not as a prerelease of `2024`
+static DATE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(\d{4})[-_](\d{1,2})[-_](\d{1,2})$").unwrap());
+
+/// Parse a release tag as semver, tolerating prefixes like `v` and missing components
+pub fn parse(tag: &str) -> Option<Version> {
+    let tag = &tag[start(tag)?..];
+    if let Some(date) = DATE.captures(tag) {
+        let number = |i: usize| date[i].parse::<u64>().ok();
+        return Some(Version::new(number(1)?, number(2)?, number(3)?));
+    }
+    if let Ok(version) = Version::parse(tag) {
+        return Some(version);
+    }
+    let captures = LOOSE.captures(tag)?;
+    let number = |i| {
+        captures
+            .get(i)
+            .map_or(Some(0), |m: regex::Match| m.as_str().parse().ok())
+    };
+    let mut version = Version::new(number(1)?, number(2)?, number(3)?);
+    if let Some(pre) = captures.get(4) {
+        version.pre = Prerelease::new(pre.as_str()).ok()?;
+    }
+    Some(version)
+}
+
+// The version starts at the beginning, after a separator or after a `v` prefix, so digits in a
+// name like `tool2-1.0.0` are skipped
+fn start(tag: &str) -> Option<usize> {
+    let separator = |c: Option<char>| c.is_none_or(|c| "-_/@".contains(c));
+    tag.char_indices().find_map(|(i, c)| {
+        let mut before = tag[..i].chars().rev();
+        let prev = before.next();
+        let prefixed = matches!(prev, Some('v' | 'V')) && separator(before.next());
+        (c.is_ascii_digit() && (separator(prev) || prefixed)).then_some(i)
+    })
+}
+
+pub fn constraint(req: Option<&str>) -> anyhow::Result<VersionReq> {
+    match req {
+        Some(req) => VersionReq::parse(req)
+            .map_err(|e| anyhow::anyhow!("Invalid version constraint `{}`: {}", req, e)),
+        None => Ok(VersionReq::STAR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tags() {
+        for (tag, expected) in [
+            ("1.2.3", "1.2.3"),
+            ("v1.2.3", "1.2.3"),
+            ("V2.0", "2.0.0"),
+            ("15", "15.0.0"),
+            ("v1.4.0-rc.1", "1.4.0-rc.1"),
+            ("1.4rc1", "1.4.0-rc1"),
+            ("tool-v0.9.1", "0.9.1"),
+            ("tool2-1.0.0", "1.0.0"),
+            ("mold2_3.1", "3.1.0"),
+            ("cli/v3.2.1", "3.2.1"),
+            ("pkg@4.5.6", "4.5.6"),
+            ("rev2-1.0", "1.0.0"),
+            ("2024-01-05", "2024.1.5"),
+            ("release-2024_12_31", "2024.12.31"),
+        ] {
+            assert_eq!(parse(tag), Version::parse(expected).ok(), "{}", tag);
+        }
+        for tag in ["nightly", "tool2", "latest-v"] {
+            assert_eq!(parse(tag), None, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn constraints() {
+        let caret = constraint(Some("^1.4")).unwrap();
+        assert!(caret.matches(&parse("v1.9.0").unwrap()));
+        assert!(!caret.matches(&parse("v2.0.0").unwrap()));
+        assert!(!caret.matches(&parse("v1.3.9").unwrap()));
+        let tilde = constraint(Some("~0.9")).unwrap();
+        assert!(tilde.matches(&parse("0.9.7").unwrap()));
+        assert!(!tilde.matches(&parse("0.10.0").unwrap()));
+        assert!(!caret.matches(&parse("tool2-1.0.0").unwrap()));
+        assert!(constraint(None).unwrap().matches(&parse("0.0.1").unwrap()));
+        assert!(constraint(Some("not a constraint")).is_err());
+    }
+}
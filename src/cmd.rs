@@ -8,6 +8,31 @@ use crate::bspm::{self, *};
 
 #[derive(StructOpt, Debug)]
 #[structopt(about = "The blindspot package manager")]
+pub struct Cli {
+    #[structopt(
+        long = "non-interactive",
+        visible_alias = "yes",
+        short = "y",
+        global = true,
+        help = "Never ask questions, use defaults and remembered choices or fail (implied if STDIN is not a TTY)"
+    )]
+    non_interactive: bool,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+impl Cli {
+    pub async fn go(&self) -> anyhow::Result<()> {
+        if self.non_interactive {
+            ui::set_interactive(false);
+        }
+        self.command.go().await
+    }
+}
+
+// Parsed once per run, boxing the install options is not worth it
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt, Debug)]
 pub enum Command {
     #[structopt(
         name = "init",
@@ -25,7 +50,7 @@ pub enum Command {
         #[structopt(help = "Name of the package. This can be anything you want.")]
         name: String,
         #[structopt(
-            help = "Either a direct http download URL (the URL should not change over time and always provide the latest version) or a github repo in the form of `username/repository`."
+            help = "Either a direct http download URL (the URL should not change over time and always provide the latest version), a URL template with placeholders like `{version}`, `{os}` and `{arch}` or a github repo in the form of `username/repository`."
         )]
         url: String,
         #[structopt(short, long, help = "Install anyways and overwrite existing versions")]
@@ -34,6 +59,85 @@ pub enum Command {
         compression: Option<bspm::installer::Compression>,
         #[structopt(help = "Set archive type", short, long, possible_values = &bspm::installer::Archived::variants(), case_insensitive = false)]
         archive: Option<bspm::installer::Archived>,
+        #[structopt(
+            long,
+            help = "Pick the release asset that fits this host best without asking (GitHub, GitLab and Gitea)"
+        )]
+        auto: bool,
+        #[structopt(
+            long,
+            conflicts_with = "signify-key",
+            help = "Verify downloads with this minisign public key (requires a `.minisig` file next to the download)"
+        )]
+        minisign_key: Option<String>,
+        #[structopt(
+            long,
+            help = "Verify downloads with this signify public key (requires a `.sig` file next to the download)"
+        )]
+        signify_key: Option<String>,
+        #[structopt(
+            long,
+            help = "Verify downloads with the OpenPGP signature (`.asc` file next to the download). The signing key is looked up on keys.openpgp.org and pinned on first use"
+        )]
+        openpgp: bool,
+        #[structopt(
+            long,
+            help = "Use the OpenPGP key from this URL or file instead of looking it up (implies --openpgp)"
+        )]
+        openpgp_key: Option<String>,
+        #[structopt(
+            long,
+            help = "Install this release tag instead of the latest one and pin the package to it (not for plain download URLs)"
+        )]
+        tag: Option<String>,
+        #[structopt(
+            long,
+            conflicts_with = "tag",
+            help = "Only install and update to releases matching this semver requirement, like `^1.4` or `~0.9` (not for plain download URLs)"
+        )]
+        constraint: Option<String>,
+        #[structopt(
+            long,
+            default_value = "stable",
+            possible_values = &bspm::Channel::variants(),
+            help = "Release channel to follow (not for plain download URLs)"
+        )]
+        channel: bspm::Channel,
+        #[structopt(
+            long,
+            conflicts_with = "versions-url",
+            help = "Take the version of a download URL or the `{version}` of a URL template from the releases of this GitHub, GitLab or Gitea repository"
+        )]
+        versions: Option<String>,
+        #[structopt(
+            long,
+            help = "Read the versions of a download URL from this web page or JSON API (requires --versions-json or --versions-regex)"
+        )]
+        versions_url: Option<String>,
+        #[structopt(
+            long,
+            requires = "versions-url",
+            conflicts_with = "versions-regex",
+            help = "JSON pointer to the version in the document at --versions-url, like `/tag_name`"
+        )]
+        versions_json: Option<String>,
+        #[structopt(
+            long,
+            requires = "versions-url",
+            help = "Regex matching the versions on the page at --versions-url, the first capture group is used if there is one"
+        )]
+        versions_regex: Option<String>,
+        #[structopt(
+            long,
+            help = "Unpack the whole archive into the data dir and link the chosen executables into the bin dir, for tools that need their bundled files"
+        )]
+        tree: bool,
+        #[structopt(
+            long,
+            requires = "tree",
+            help = "Unpack only this top-level directory of the archive, may contain wildcards like `node-v*`"
+        )]
+        tree_root: Option<String>,
     },
     #[structopt(
         name = "remove",
@@ -45,15 +149,47 @@ pub enum Command {
         #[structopt(help = "Name of the package")]
         name: String,
     },
-    #[structopt(name = "revert", about = "Revert the last update of a package")]
+    #[structopt(
+        name = "revert",
+        about = "Revert a package to a previously installed release"
+    )]
     Revert {
-        #[structopt(help = "Name of the package (only works once after every update)")]
+        #[structopt(help = "Name of the package")]
+        name: String,
+        #[structopt(long, help = "Release to revert to (defaults to the previous one)")]
+        to: Option<String>,
+    },
+    #[structopt(
+        name = "history",
+        about = "List the releases kept for reverting a package"
+    )]
+    History {
+        #[structopt(help = "Name of the package")]
+        name: String,
+    },
+    #[structopt(
+        name = "pin",
+        about = "Hold a package at a release, updates will skip it"
+    )]
+    Pin {
+        #[structopt(help = "Name of the package")]
+        name: String,
+        #[structopt(
+            help = "Release tag to pin to, the next update installs it (defaults to the installed release)"
+        )]
+        tag: Option<String>,
+    },
+    #[structopt(name = "unpin", about = "Let updates upgrade a pinned package again")]
+    Unpin {
+        #[structopt(help = "Name of the package")]
         name: String,
     },
     #[structopt(name = "update", about = "Update installed packages")]
     Update {
         #[structopt(help = "List of packages to update")]
         packages: Vec<String>,
+        #[structopt(long, help = "Allow updating to releases older than the installed one")]
+        allow_downgrade: bool,
     },
     #[structopt(name = "list", about = "List currently installed packages")]
     List {
@@ -81,9 +217,7 @@ impl Command {
                         .install(
                             "blindspot".to_string(),
                             "xermicus/blindspot".to_string(),
-                            false,
-                            None,
-                            None,
+                            InstallOptions::default(),
                         )
                         .await?;
                 }
@@ -100,25 +234,84 @@ impl Command {
                 force,
                 compression,
                 archive,
+                auto,
+                minisign_key,
+                signify_key,
+                openpgp,
+                openpgp_key,
+                tag,
+                constraint,
+                channel,
+                versions,
+                versions_url,
+                versions_json,
+                versions_regex,
+                tree,
+                tree_root,
             } => {
+                let signature = match (minisign_key, signify_key) {
+                    (Some(key), _) => Some(verify::Signature {
+                        kind: verify::SignatureKind::Minisign,
+                        public_key: key.clone(),
+                    }),
+                    (_, Some(key)) => Some(verify::Signature {
+                        kind: verify::SignatureKind::Signify,
+                        public_key: key.clone(),
+                    }),
+                    _ => None,
+                };
                 bspm?
                     .install(
                         name.clone(),
                         url.clone(),
-                        *force,
-                        compression.clone(),
-                        archive.clone(),
+                        InstallOptions {
+                            force: *force,
+                            compression: compression.clone(),
+                            archive: archive.clone(),
+                            auto: *auto,
+                            signature,
+                            openpgp: if *openpgp || openpgp_key.is_some() {
+                                Some(bspm::openpgp::OpenPgp {
+                                    key: openpgp_key.clone(),
+                                    fingerprint: None,
+                                })
+                            } else {
+                                None
+                            },
+                            tag: tag.clone(),
+                            constraint: constraint.clone(),
+                            channel: *channel,
+                            versions: versions.clone(),
+                            versions_url: versions_url.clone(),
+                            versions_json: versions_json.clone(),
+                            versions_regex: versions_regex.clone(),
+                            tree: *tree,
+                            tree_root: tree_root.clone(),
+                        },
                     )
                     .await?;
             }
             Command::Remove { name } => {
                 bspm?.delete(name).await?;
             }
-            Command::Revert { name } => {
-                bspm?.revert(name).await?;
+            Command::Revert { name, to } => {
+                bspm?.revert(name, to.as_deref()).await?;
+            }
+            Command::History { name } => {
+                bspm?.history(name)?;
+                return Ok(());
             }
-            Command::Update { packages } => {
-                bspm?.update(packages.to_vec()).await?;
+            Command::Pin { name, tag } => {
+                bspm?.pin(name, tag.as_deref()).await?;
+            }
+            Command::Unpin { name } => {
+                bspm?.unpin(name).await?;
+            }
+            Command::Update {
+                packages,
+                allow_downgrade,
+            } => {
+                bspm?.update(packages.to_vec(), *allow_downgrade).await?;
             }
             Command::List { debug } => {
                 if *debug {
@@ -131,7 +324,7 @@ impl Command {
             Command::Completion { shell } => {
                 let stdout = io::stdout();
                 let mut handle = stdout.lock();
-                Command::clap().gen_completions_to(env!("CARGO_PKG_NAME"), *shell, &mut handle);
+                Cli::clap().gen_completions_to(env!("CARGO_PKG_NAME"), *shell, &mut handle);
                 return Ok(());
             }
         }
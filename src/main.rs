@@ -2,11 +2,11 @@
 extern crate serde_derive;
 use structopt::StructOpt;
 
-use cmd::Command;
+use cmd::Cli;
 
 mod bspm;
 mod cmd;
 
 fn main() -> anyhow::Result<()> {
-    smol::block_on(async { Command::from_args().go().await })
+    smol::block_on(async { Cli::from_args().go().await })
 }